@@ -0,0 +1,5 @@
+// sqlx::migrate! embeds migrations/ at compile time; rebuild when a new
+// migration is added even if no Rust source changed.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -21,7 +21,6 @@
             pkg-config
             openssl
             sqlite
-            protobuf
             rust-analyzer
             jq
             postgresql
@@ -45,8 +45,33 @@ CREATE TEMP TABLE stops_import (LIKE stops);
 INSERT INTO stops SELECT * FROM stops_import ON CONFLICT DO NOTHING;
 SQL
 
+# Indexes are rebuilt once after the load instead of maintained per row. The
+# unique index stays: it's what skips rows already logged.
+echo "==> Dropping departure_log indexes for bulk load..."
+psql "$PG_URL" -c "DROP INDEX IF EXISTS idx_log_polled_at; DROP INDEX IF EXISTS idx_log_stop; DROP INDEX IF EXISTS idx_log_trip;"
+
 echo "==> Loading departure_log into Postgres (this may take a moment)..."
-psql "$PG_URL" -c "\COPY departure_log (polled_at, global_stop_id, global_route_id, route_short_name, headsign, departure_time, scheduled_departure_time, delay_seconds, is_real_time, is_cancelled, rt_trip_id) FROM '/tmp/bbb_departure_log.csv' CSV"
+psql "$PG_URL" <<'SQL'
+CREATE TEMP TABLE departure_log_import AS
+SELECT polled_at, global_stop_id, global_route_id, route_short_name, headsign,
+       departure_time, scheduled_departure_time, delay_seconds,
+       is_real_time, is_cancelled, rt_trip_id
+FROM departure_log WITH NO DATA;
+\COPY departure_log_import (polled_at, global_stop_id, global_route_id, route_short_name, headsign, departure_time, scheduled_departure_time, delay_seconds, is_real_time, is_cancelled, rt_trip_id) FROM '/tmp/bbb_departure_log.csv' CSV
+-- Rows already in Postgres, or repeated in the export, are skipped
+INSERT INTO departure_log (polled_at, global_stop_id, global_route_id, route_short_name, headsign,
+                           departure_time, scheduled_departure_time, delay_seconds,
+                           is_real_time, is_cancelled, rt_trip_id)
+SELECT * FROM departure_log_import
+ON CONFLICT DO NOTHING;
+SQL
+
+echo "==> Recreating departure_log indexes..."
+psql "$PG_URL" <<'SQL'
+CREATE INDEX IF NOT EXISTS idx_log_polled_at ON departure_log(polled_at);
+CREATE INDEX IF NOT EXISTS idx_log_stop ON departure_log(global_stop_id, departure_time);
+CREATE INDEX IF NOT EXISTS idx_log_trip ON departure_log(rt_trip_id, polled_at);
+SQL
 
 echo "==> Verifying..."
 psql "$PG_URL" -c "SELECT COUNT(*) AS stops FROM stops;"
@@ -0,0 +1,26 @@
+-- IF NOT EXISTS so databases created before migrations were tracked pick
+-- this up as already applied instead of failing.
+CREATE TABLE IF NOT EXISTS stops (
+    global_stop_id TEXT PRIMARY KEY,
+    stop_name TEXT NOT NULL,
+    lat DOUBLE PRECISION NOT NULL,
+    lon DOUBLE PRECISION NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS departure_log (
+    id BIGSERIAL PRIMARY KEY,
+    polled_at BIGINT NOT NULL,
+    global_stop_id TEXT NOT NULL,
+    global_route_id TEXT NOT NULL,
+    route_short_name TEXT NOT NULL,
+    headsign TEXT,
+    departure_time BIGINT NOT NULL,
+    scheduled_departure_time BIGINT NOT NULL,
+    delay_seconds INTEGER,
+    is_real_time BOOLEAN NOT NULL DEFAULT FALSE,
+    is_cancelled BOOLEAN NOT NULL DEFAULT FALSE,
+    rt_trip_id TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_log_polled_at ON departure_log(polled_at);
+CREATE INDEX IF NOT EXISTS idx_log_stop ON departure_log(global_stop_id, departure_time);
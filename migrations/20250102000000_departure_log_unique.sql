@@ -0,0 +1,12 @@
+-- Rows logged before the unique index existed may contain duplicates, which
+-- would make CREATE UNIQUE INDEX fail. Keep the oldest copy of each.
+DELETE FROM departure_log a USING departure_log b
+WHERE a.id > b.id
+  AND a.polled_at = b.polled_at
+  AND a.global_stop_id = b.global_stop_id
+  AND a.global_route_id = b.global_route_id
+  AND a.scheduled_departure_time = b.scheduled_departure_time
+  AND COALESCE(a.rt_trip_id, '') = COALESCE(b.rt_trip_id, '');
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_log_unique ON departure_log
+    (polled_at, global_stop_id, global_route_id, scheduled_departure_time, COALESCE(rt_trip_id, ''));
@@ -0,0 +1,2 @@
+-- Backs the per-trip lookups (departures_for_trip, latest_for_trip)
+CREATE INDEX IF NOT EXISTS idx_log_trip ON departure_log(rt_trip_id, polled_at);
@@ -0,0 +1,8 @@
+-- Which stops each bootstrapped route serves, so the poll list can follow
+-- the configured routes. Routes bootstrapped before this table existed have
+-- no rows here; the server bootstraps them again on startup.
+CREATE TABLE IF NOT EXISTS route_stops (
+    global_route_id TEXT NOT NULL,
+    global_stop_id TEXT NOT NULL REFERENCES stops(global_stop_id),
+    PRIMARY KEY (global_route_id, global_stop_id)
+);
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::models::{format_delay, Departure};
+
+/// Delay summary for one route.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DelayStats {
+    pub mean: f64,
+    /// Departures that had a real-time delay
+    pub count: usize,
+    pub max: i32,
+}
+
+/// Mean, sample count and worst delay per `global_route_id` (short names
+/// collide across agencies). Departures without a real-time delay are left
+/// out rather than counted as on time.
+pub fn average_delay_by_route(departures: &[Departure]) -> HashMap<String, DelayStats> {
+    let mut totals: HashMap<&str, (i64, usize, i32)> = HashMap::new();
+    for d in departures {
+        let Some(delay) = d.delay_seconds else { continue };
+        let (sum, count, max) = totals.entry(&d.global_route_id).or_insert((0, 0, i32::MIN));
+        *sum += delay as i64;
+        *count += 1;
+        *max = (*max).max(delay);
+    }
+
+    totals
+        .into_iter()
+        .map(|(route, (sum, count, max))| {
+            let stats = DelayStats { mean: sum as f64 / count as f64, count, max };
+            (route.to_string(), stats)
+        })
+        .collect()
+}
+
+/// A trip whose worst real-time delay across its stops is over a threshold.
+#[derive(Debug, Clone, Serialize)]
+pub struct LateTrip {
+    pub rt_trip_id: String,
+    pub route_short_name: String,
+    pub headsign: Option<String>,
+    pub max_delay_seconds: i32,
+}
+
+/// Trips whose largest delay at any stop exceeds `threshold_seconds`, worst
+/// first. Early running (negative delay) never counts, and departures with no
+/// trip ID can't be grouped so are skipped.
+pub fn trips_exceeding_delay(departures: &[Departure], threshold_seconds: i32) -> Vec<LateTrip> {
+    let mut worst: HashMap<&str, (&Departure, i32)> = HashMap::new();
+    for d in departures {
+        let (Some(trip), Some(delay)) = (d.rt_trip_id.as_deref(), d.delay_seconds) else { continue };
+        let entry = worst.entry(trip).or_insert((d, delay));
+        if delay > entry.1 {
+            *entry = (d, delay);
+        }
+    }
+
+    let mut late: Vec<LateTrip> = worst
+        .into_iter()
+        .filter(|(_, (_, delay))| *delay > threshold_seconds.max(0))
+        .map(|(trip, (d, delay))| LateTrip {
+            rt_trip_id: trip.to_string(),
+            route_short_name: d.route_short_name.clone(),
+            headsign: d.headsign.clone(),
+            max_delay_seconds: delay,
+        })
+        .collect();
+    late.sort_by_key(|t| std::cmp::Reverse(t.max_delay_seconds));
+    late
+}
+
+/// Delay spread across the stops of one trip.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DelaySummary {
+    pub min: i32,
+    pub max: i32,
+    pub mean: f64,
+    /// Stops that had a real-time delay
+    pub stops: usize,
+}
+
+impl DelaySummary {
+    /// Summary over the departures that carry a real-time delay, or `None`
+    /// if none do.
+    pub fn from_departures<'a>(departures: impl IntoIterator<Item = &'a Departure>) -> Option<Self> {
+        let delays: Vec<i32> = departures.into_iter().filter_map(|d| d.delay_seconds).collect();
+        let (min, max) = (*delays.iter().min()?, *delays.iter().max()?);
+        let mean = delays.iter().map(|&d| d as f64).sum::<f64>() / delays.len() as f64;
+        Some(Self { min, max, mean, stops: delays.len() })
+    }
+}
+
+impl fmt::Display for DelaySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "avg {}, worst {}", format_delay(self.mean.round() as i32), format_delay(self.max))
+    }
+}
+
+/// `DelaySummary` per `rt_trip_id`, for trips with at least one real-time
+/// delay. Departures without a trip ID are skipped.
+pub fn delay_summary_by_trip(departures: &[Departure]) -> HashMap<String, DelaySummary> {
+    let mut trips: HashMap<&str, Vec<&Departure>> = HashMap::new();
+    for d in departures {
+        if let Some(trip) = d.rt_trip_id.as_deref() {
+            trips.entry(trip).or_default().push(d);
+        }
+    }
+    trips
+        .into_iter()
+        .filter_map(|(trip, deps)| Some((trip.to_string(), DelaySummary::from_departures(deps)?)))
+        .collect()
+}
+
+/// Count real-time delays in `bucket_seconds`-wide bins keyed by each bin's
+/// lower bound, so with 60 s bins a delay of -30 s lands in `-60` and 30 s in
+/// `0`. Departures without a real-time delay are skipped.
+pub fn delay_histogram(departures: &[Departure], bucket_seconds: i32) -> BTreeMap<i32, usize> {
+    let width = bucket_seconds.max(1);
+    let mut bins = BTreeMap::new();
+    for delay in departures.iter().filter_map(|d| d.delay_seconds) {
+        *bins.entry(delay.div_euclid(width) * width).or_insert(0) += 1;
+    }
+    bins
+}
+
+/// One line per bin of a `delay_histogram`, with a `#` bar scaled so the
+/// fullest bin is `bar_width` wide:
+///
+/// ```text
+///       -60s..0s ########                 2
+///        0s..60s ####################     5
+/// ```
+pub fn render_histogram(bins: &BTreeMap<i32, usize>, bucket_seconds: i32, bar_width: usize) -> String {
+    let width = bucket_seconds.max(1);
+    let peak = bins.values().copied().max().unwrap_or(0).max(1);
+    let mut out = String::new();
+    for (&low, &count) in bins {
+        let label = format!("{}s..{}s", low, low + width);
+        let bar = "#".repeat((count * bar_width).div_ceil(peak));
+        out.push_str(&format!("{:>14} {:<w$} {:>5}\n", label, bar, count, w = bar_width));
+    }
+    out
+}
+
+/// Common agency tolerance: up to 1 minute early or 5 minutes late is on time.
+pub const DEFAULT_OTP_EARLY_SECS: i32 = 60;
+pub const DEFAULT_OTP_LATE_SECS: i32 = 300;
+
+/// On-time performance: how many departures with a real-time delay fell
+/// inside the tolerance window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct OnTimePerformance {
+    pub on_time: usize,
+    pub samples: usize,
+}
+
+impl OnTimePerformance {
+    /// Fraction on time, or `None` with no samples.
+    pub fn fraction(&self) -> Option<f64> {
+        (self.samples > 0).then(|| self.on_time as f64 / self.samples as f64)
+    }
+}
+
+/// On time means a delay in `[-early_tol_secs, late_tol_secs]`, both ends
+/// inclusive. Departures without a real-time delay are excluded rather than
+/// counted either way.
+pub fn on_time_performance(
+    departures: &[Departure],
+    early_tol_secs: i32,
+    late_tol_secs: i32,
+) -> OnTimePerformance {
+    let window = -early_tol_secs.saturating_abs()..=late_tol_secs.saturating_abs();
+    let delays: Vec<i32> = departures.iter().filter_map(|d| d.delay_seconds).collect();
+    OnTimePerformance {
+        on_time: delays.iter().filter(|d| window.contains(d)).count(),
+        samples: delays.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delays(delays: &[Option<i32>]) -> Vec<Departure> {
+        delays.iter().map(|&d| Departure::sample().trip("1001").delay(d)).collect()
+    }
+
+    #[test]
+    fn on_time_window_is_inclusive() {
+        let departures = delays(&[Some(-61), Some(-60), Some(0), Some(300), Some(301), None]);
+        let otp = on_time_performance(&departures, DEFAULT_OTP_EARLY_SECS, DEFAULT_OTP_LATE_SECS);
+        assert_eq!(otp, OnTimePerformance { on_time: 3, samples: 5 });
+        assert_eq!(otp.fraction(), Some(0.6));
+
+        // The sign of the early tolerance doesn't matter
+        assert_eq!(on_time_performance(&departures, -60, 300), otp);
+        // i32::MIN has no positive counterpart; it widens to i32::MAX instead of panicking
+        let everything = on_time_performance(&departures, i32::MIN, i32::MIN);
+        assert_eq!(everything, OnTimePerformance { on_time: 5, samples: 5 });
+    }
+
+    #[test]
+    fn on_time_without_real_time_delays() {
+        let otp = on_time_performance(&delays(&[None, None]), 60, 300);
+        assert_eq!(otp, OnTimePerformance::default());
+        assert_eq!(otp.fraction(), None);
+        assert_eq!(on_time_performance(&[], 60, 300).fraction(), None);
+    }
+
+    #[test]
+    fn delay_summary_per_trip() {
+        let departures = [
+            Departure::sample().trip("1001").delay(-30),
+            Departure::sample().stop("BBB:7024").trip("1001").delay(90),
+            Departure::sample().stop("BBB:7025").trip("1001").delay(None),
+            Departure::sample().trip("1002").delay(600),
+            // Schedule-only all along, and no trip ID to group by
+            Departure::sample().trip("1003").delay(None),
+            Departure::sample().delay(900),
+        ];
+        let summaries = delay_summary_by_trip(&departures);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries["1001"], DelaySummary { min: -30, max: 90, mean: 30.0, stops: 2 });
+        assert_eq!(summaries["1002"], DelaySummary { min: 600, max: 600, mean: 600.0, stops: 1 });
+        assert_eq!(summaries["1001"].to_string(), "avg 30s late, worst 1m 30s late");
+    }
+
+    #[test]
+    fn histogram_bins_by_lower_bound() {
+        let departures = delays(&[Some(-61), Some(-60), Some(-1), Some(0), Some(59), Some(60), None]);
+        let bins = delay_histogram(&departures, 60);
+        assert_eq!(bins, BTreeMap::from([(-120, 1), (-60, 2), (0, 2), (60, 1)]));
+
+        // A zero or negative width falls back to 1 s bins
+        let bins = delay_histogram(&delays(&[Some(-1), Some(0), Some(0)]), 0);
+        assert_eq!(bins, BTreeMap::from([(-1, 1), (0, 2)]));
+        assert!(delay_histogram(&delays(&[None]), 60).is_empty());
+    }
+
+    #[test]
+    fn histogram_bars_scale_to_the_fullest_bin() {
+        let bins = BTreeMap::from([(-60, 2), (0, 5), (60, 1)]);
+        let rendered = render_histogram(&bins, 60, 10);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines, [
+            "      -60s..0s ####           2",
+            "       0s..60s ##########     5",
+            "     60s..120s ##             1",
+        ]);
+        assert_eq!(render_histogram(&BTreeMap::new(), 60, 10), "");
+    }
+}
@@ -1,14 +1,103 @@
-use crate::models::{Departure, Stop};
+use crate::archive::FeedArchive;
+use crate::filter::{DepartureFilter, RouteMatcher};
+use crate::models::{BoardEntry, Departure, FeedTime, Stop};
 use anyhow::{Context, Result};
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::Deserialize;
+use std::time::Duration;
 
-const TRANSIT_API_BASE: &str = "https://external.transitapp.com";
+pub const DEFAULT_API_BASE: &str = "https://external.transitapp.com";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
 const DISCOVERY_LAT: f64 = 34.04363632;
 const DISCOVERY_LON: f64 = -118.45709929;
 
+/// The API host couldn't be resolved. Usually the machine has no working
+/// network (captive-portal wifi, DNS down), not a Transit API outage, so the
+/// request is safe to retry once connectivity returns.
+#[derive(Debug)]
+pub struct DnsResolutionError {
+    pub host: String,
+}
+
+impl std::fmt::Display for DnsResolutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "could not resolve {} — check the network connection (captive portal or DNS outage?)",
+            self.host
+        )
+    }
+}
+
+impl std::error::Error for DnsResolutionError {}
+
+fn is_dns_error(err: &reqwest::Error) -> bool {
+    let mut source: Option<&dyn std::error::Error> = Some(err);
+    while let Some(e) = source {
+        let msg = e.to_string();
+        if msg.contains("dns error") || msg.contains("failed to lookup address") {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Wrap a failed `send()`, pulling DNS failures out of reqwest's error chain.
+fn request_error(err: reqwest::Error, endpoint: &str) -> anyhow::Error {
+    let context = format!("Failed to call {}", endpoint);
+    if err.is_connect() && is_dns_error(&err) {
+        let host = err
+            .url()
+            .and_then(|u| u.host_str())
+            .unwrap_or("unknown host")
+            .to_string();
+        return anyhow::Error::new(DnsResolutionError { host }).context(context);
+    }
+    anyhow::Error::new(err).context(context)
+}
+
+/// How hard to retry a request that failed in transit or got a 5xx.
+/// 4xx responses (bad key, quota exhausted) are never retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total tries including the first; 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self { max_attempts: 1, base_delay: Duration::ZERO }
+    }
+
+    /// Exponential backoff plus up to 50% random jitter.
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(1 << (attempt - 1).min(16));
+        let jitter_ms = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64 / 2);
+        backoff + Duration::from_millis(jitter_ms)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 3, base_delay: Duration::from_millis(500) }
+    }
+}
+
 pub struct TransitClient {
     client: reqwest::Client,
     api_key: String,
+    base_url: String,
+    retry: RetryPolicy,
+    archive: Option<FeedArchive>,
+    parse_options: ParseOptions,
+    /// Sent on every request, marked sensitive
+    extra_headers: HeaderMap,
+    /// Appended to every request; values are redacted from logged URLs
+    extra_query: Vec<(String, String)>,
 }
 
 // --- route_details response ---
@@ -64,8 +153,9 @@ struct RouteItinerary {
 
 #[derive(Deserialize)]
 struct ScheduleItem {
-    departure_time: i64,
-    scheduled_departure_time: Option<i64>,
+    /// Missing or 0 when the API has no time for the item; those are dropped
+    departure_time: Option<FeedTime>,
+    scheduled_departure_time: Option<FeedTime>,
     #[serde(default)]
     is_real_time: bool,
     #[serde(default)]
@@ -76,8 +166,8 @@ struct ScheduleItem {
 // --- nearby_stops response (resolve-stops discovery) ---
 
 #[derive(Deserialize)]
-pub struct NearbyStopsResponse {
-    pub stops: Vec<NearbyStop>,
+struct NearbyStopsResponse {
+    stops: Vec<NearbyStop>,
 }
 
 #[derive(Deserialize)]
@@ -102,33 +192,411 @@ struct DiscoverRoute {
     route_network_name: Option<String>,
 }
 
+/// Name of the compression format `body` starts with, if any. reqwest only
+/// decodes bodies labelled with Content-Encoding, so an unlabelled one reaches
+/// the JSON parser as binary and would otherwise fail with a confusing error.
+/// (Brotli has no magic bytes and can't be recognised.)
+fn compressed_encoding(body: &[u8]) -> Option<&'static str> {
+    match body {
+        [0x1f, 0x8b, ..] => Some("gzip"),
+        [0x78, 0x01 | 0x5e | 0x9c | 0xda, ..] => Some("deflate"),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Some("zstd"),
+        _ => None,
+    }
+}
+
+/// Which departures `parse_stop_departures_with` drops. The default keeps
+/// everything, as `parse_stop_departures` always has.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    /// Drop departures with no `rt_trip_id`; they can't be followed across
+    /// stops or polls
+    pub require_trip_id: bool,
+    /// Drop schedule-only departures that carry no real-time prediction
+    pub require_real_time: bool,
+}
+
+/// Turn a raw stop_departures response body into departures, soonest first.
+/// Kept free of I/O so captured responses can be replayed without a network.
+///
+/// Items with a missing or zero `departure_time` are dropped rather than
+/// logged at the epoch, and a zero `scheduled_departure_time` counts as
+/// missing. A route with no schedule items contributes nothing.
+pub fn parse_stop_departures(body: &[u8]) -> Result<Vec<Departure>> {
+    parse_stop_departures_with(body, &ParseOptions::default())
+}
+
+/// `parse_stop_departures`, dropping whatever `options` asks to.
+pub fn parse_stop_departures_with(body: &[u8], options: &ParseOptions) -> Result<Vec<Departure>> {
+    if let Some(encoding) = compressed_encoding(body) {
+        anyhow::bail!(
+            "stop_departures response is still {}-compressed (a proxy likely dropped Content-Encoding); \
+             decompress it before parsing",
+            encoding
+        );
+    }
+    let body: StopDeparturesResponse =
+        serde_json::from_slice(body).context("Failed to parse stop_departures response")?;
+
+    let mut departures = Vec::new();
+    let mut skipped = 0;
+    let mut untimed = 0;
+
+    for route_dep in body.route_departures {
+        for merged in route_dep.merged_itineraries {
+            let headsign = merged
+                .itineraries
+                .first()
+                .and_then(|i| i.merged_headsign.clone().or_else(|| i.headsign.clone()));
+
+            for item in merged.schedule_items {
+                let Some(departure_time) = item.departure_time.filter(|t| t.is_set()) else {
+                    untimed += 1;
+                    continue;
+                };
+                if (options.require_trip_id && item.rt_trip_id.is_none())
+                    || (options.require_real_time && !item.is_real_time)
+                {
+                    skipped += 1;
+                    continue;
+                }
+                let scheduled = item
+                    .scheduled_departure_time
+                    .filter(|t| t.is_set())
+                    .unwrap_or(departure_time);
+                let delay_seconds = item
+                    .is_real_time
+                    .then(|| (departure_time.secs() - scheduled.secs()) as i32);
+
+                departures.push(Departure {
+                    global_stop_id: route_dep.global_stop_id.clone(),
+                    global_route_id: route_dep.global_route_id.clone(),
+                    route_short_name: route_dep.route_short_name.clone(),
+                    headsign: headsign.clone(),
+                    departure_time,
+                    scheduled_departure_time: scheduled,
+                    delay_seconds,
+                    is_real_time: item.is_real_time,
+                    is_cancelled: item.is_cancelled,
+                    rt_trip_id: item.rt_trip_id,
+                });
+            }
+        }
+    }
+
+    if untimed > 0 {
+        tracing::debug!(untimed, "Dropped departures with no departure time");
+    }
+    if skipped > 0 {
+        tracing::debug!(skipped, kept = departures.len(), "Dropped departures per parse options");
+    }
+    departures.sort_by_key(|d| d.departure_time);
+    Ok(departures)
+}
+
+/// Parse a captured stop_departures response from disk.
+pub fn parse_file(path: &std::path::Path) -> Result<Vec<Departure>> {
+    let body = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_stop_departures(&body).with_context(|| format!("Failed to decode {}", path.display()))
+}
+
+/// Options for a `TransitClient`, checked together by `build` so a bad
+/// combination fails at startup instead of on the first poll.
+///
+/// ```no_run
+/// # use bigbluebunch::api::{RetryPolicy, TransitClient};
+/// # use std::time::Duration;
+/// let client = TransitClient::builder("key")
+///     .base_url("http://127.0.0.1:8118")
+///     .timeout(Duration::from_secs(5))
+///     .retry_policy(RetryPolicy::none())
+///     .header("Authorization", "Bearer abc")
+///     .build()?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug)]
+pub struct TransitClientBuilder {
+    api_key: String,
+    base_url: String,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    headers: Vec<(String, String)>,
+    query: Vec<(String, String)>,
+    parse_options: ParseOptions,
+    archive: Option<FeedArchive>,
+    http_client: Option<reqwest::Client>,
+}
+
+impl TransitClientBuilder {
+    fn new(api_key: String) -> Self {
+        Self {
+            api_key,
+            base_url: DEFAULT_API_BASE.to_string(),
+            timeout: None,
+            retry: RetryPolicy::default(),
+            headers: Vec::new(),
+            query: Vec::new(),
+            parse_options: ParseOptions::default(),
+            archive: None,
+            http_client: None,
+        }
+    }
+
+    /// Must be an http(s) URL; checked by `build`.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// Per-request timeout (default 10 s). Can't be combined with `http_client`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// See `TransitClient::with_header`.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// See `TransitClient::with_query_param`.
+    pub fn query_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn parse_options(mut self, options: ParseOptions) -> Self {
+        self.parse_options = options;
+        self
+    }
+
+    pub fn archive(mut self, archive: FeedArchive) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Use a caller-built `reqwest::Client`; see `TransitClient::from_client`.
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    pub fn build(self) -> Result<TransitClient> {
+        if self.timeout == Some(Duration::ZERO) {
+            anyhow::bail!("Transit API timeout must be greater than zero");
+        }
+        if self.retry.max_attempts == 0 {
+            anyhow::bail!("Retry policy needs at least one attempt");
+        }
+        let http_client = match (self.http_client, self.timeout) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("Set the timeout on the reqwest::Client passed to http_client, not the builder")
+            }
+            (Some(client), None) => client,
+            // Advertise and transparently decode compressed responses
+            (None, timeout) => reqwest::Client::builder()
+                .timeout(timeout.unwrap_or(DEFAULT_TIMEOUT))
+                .gzip(true)
+                .brotli(true)
+                .deflate(true)
+                .build()
+                .context("Failed to create HTTP client")?,
+        };
+
+        let mut client = TransitClient::from_client(http_client, self.api_key)
+            .try_with_base_url(&self.base_url)?
+            .with_retry_policy(self.retry)
+            .with_parse_options(self.parse_options);
+        for (name, value) in &self.headers {
+            client = client.with_header(name, value)?;
+        }
+        for (key, value) in self.query {
+            client = client.with_query_param(key, value);
+        }
+        if let Some(archive) = self.archive {
+            client = client.with_archive(archive);
+        }
+        Ok(client)
+    }
+}
+
 impl TransitClient {
     pub fn new(api_key: String) -> Self {
+        Self::builder(api_key)
+            .build()
+            .expect("Default Transit API client options are valid")
+    }
+
+    pub fn builder(api_key: impl Into<String>) -> TransitClientBuilder {
+        TransitClientBuilder::new(api_key.into())
+    }
+
+    /// Use a caller-built `reqwest::Client`, e.g. one with a custom proxy,
+    /// TLS roots or connector.
+    ///
+    /// reqwest 0.11 can't dial a Unix domain socket directly. For a sidecar
+    /// that exposes the API on a UDS, bridge it to a local TCP port
+    /// (`socat TCP-LISTEN:8118,fork UNIX-CONNECT:/run/transit.sock`) and route
+    /// through it with `reqwest::Proxy::all("http://127.0.0.1:8118")`.
+    pub fn from_client(client: reqwest::Client, api_key: String) -> Self {
         Self {
-            client: reqwest::Client::builder()
-                .timeout(std::time::Duration::from_secs(10))
-                .build()
-                .expect("Failed to create HTTP client"),
+            client,
             api_key,
+            base_url: DEFAULT_API_BASE.to_string(),
+            retry: RetryPolicy::default(),
+            archive: None,
+            parse_options: ParseOptions::default(),
+            extra_headers: HeaderMap::new(),
+            extra_query: Vec::new(),
         }
     }
 
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Send `name: value` on every request, e.g. an `Authorization` header for
+    /// a gateway in front of the API. The value is treated as a secret.
+    pub fn with_header(mut self, name: &str, value: &str) -> Result<Self> {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name {:?}", name))?;
+        let mut value = HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for header {}", name))?;
+        value.set_sensitive(true);
+        self.extra_headers.insert(name, value);
+        Ok(self)
+    }
+
+    /// Append `key=value` to every request's query string. The value is
+    /// replaced with `REDACTED` wherever a request URL is logged.
+    pub fn with_query_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_query.push((key.into(), value.into()));
+        self
+    }
+
+    /// Drop departures from every poll per `options` (archived bodies are
+    /// still saved whole).
+    pub fn with_parse_options(mut self, options: ParseOptions) -> Self {
+        self.parse_options = options;
+        self
+    }
+
+    /// Save every stop_departures body to `archive` before parsing it.
+    pub fn with_archive(mut self, archive: FeedArchive) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Point at a different API host (staging mirror, local recording proxy).
+    /// Not validated; prefer `try_with_base_url` for user-supplied values.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Like `with_base_url`, but rejects anything that isn't an http(s) URL
+    /// so a typo fails at startup instead of on the first poll.
+    pub fn try_with_base_url(self, base_url: &str) -> Result<Self> {
+        let url = reqwest::Url::parse(base_url)
+            .with_context(|| format!("Invalid Transit API base URL {:?}", base_url))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("Transit API base URL must be http or https, got {:?}", base_url);
+        }
+        Ok(self.with_base_url(base_url))
+    }
+
+    /// Reads `TRANSIT_API_KEY` and, if set, `TRANSIT_API_BASE`.
     pub fn from_env() -> Self {
+        Self::builder_from_env()
+            .build()
+            .unwrap_or_else(|e| panic!("TRANSIT_API_BASE: {:#}", e))
+    }
+
+    /// A builder seeded from `TRANSIT_API_KEY` and `TRANSIT_API_BASE`, for
+    /// callers layering more options on top.
+    pub fn builder_from_env() -> TransitClientBuilder {
         let api_key = std::env::var("TRANSIT_API_KEY").expect("TRANSIT_API_KEY must be set");
-        Self::new(api_key)
+        let builder = Self::builder(api_key);
+        match std::env::var("TRANSIT_API_BASE") {
+            Ok(base) => builder.base_url(base),
+            Err(_) => builder,
+        }
+    }
+
+    /// Blank out custom query param values in the URL reqwest attaches to
+    /// its errors, so they don't end up in logs.
+    fn redact(&self, mut err: reqwest::Error) -> reqwest::Error {
+        if self.extra_query.is_empty() {
+            return err;
+        }
+        if let Some(url) = err.url_mut() {
+            let pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .map(|(k, v)| {
+                    let secret = self.extra_query.iter().any(|(key, _)| *key == k);
+                    let v = if secret { "REDACTED".to_string() } else { v.into_owned() };
+                    (k.into_owned(), v)
+                })
+                .collect();
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        err
+    }
+
+    /// Send a request built by `build`, retrying transport errors and 5xx
+    /// responses per the retry policy. Other statuses go back to the caller.
+    async fn send_with_retry(
+        &self,
+        endpoint: &str,
+        build: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let attempts = self.retry.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let last = attempt == attempts;
+            let request = build().headers(self.extra_headers.clone()).query(&self.extra_query);
+            match request.send().await.map_err(|e| self.redact(e)) {
+                Ok(response) if response.status().is_server_error() => {
+                    if last {
+                        anyhow::bail!(
+                            "{} returned {} after {} attempt(s)",
+                            endpoint,
+                            response.status(),
+                            attempts
+                        );
+                    }
+                    tracing::warn!(endpoint, status = %response.status(), attempt, "Server error, retrying");
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if last => {
+                    return Err(request_error(e, endpoint)
+                        .context(format!("{} failed after {} attempt(s)", endpoint, attempts)));
+                }
+                Err(e) => tracing::warn!(endpoint, error = %e, attempt, "Request failed, retrying"),
+            }
+            tokio::time::sleep(self.retry.delay_for(attempt)).await;
+            attempt += 1;
+        }
     }
 
     /// Bootstrap: fetch all stops for a route from route_details.
     /// Deduplicates across itineraries/directions.
     pub async fn fetch_route_stops(&self, global_route_id: &str) -> Result<Vec<Stop>> {
         let response = self
-            .client
-            .get(format!("{}/v4/public/route_details", TRANSIT_API_BASE))
-            .header("apiKey", &self.api_key)
-            .query(&[("global_route_id", global_route_id)])
-            .send()
-            .await
-            .context("Failed to call route_details")?;
+            .send_with_retry("route_details", || {
+                self.client
+                    .get(format!("{}/v4/public/route_details", self.base_url))
+                    .header("apiKey", &self.api_key)
+                    .query(&[("global_route_id", global_route_id)])
+            })
+            .await?;
 
         if !response.status().is_success() {
             anyhow::bail!("route_details returned {}", response.status());
@@ -159,82 +627,101 @@ impl TransitClient {
     }
 
     /// Poll: fetch upcoming real-time departures for a batch of stop IDs (max 100 per call).
-    pub async fn fetch_stop_departures(&self, stop_ids: &[String]) -> Result<Vec<Departure>> {
-        let stop_ids_param = stop_ids.join(",");
+    /// Accepts `&["BBB:1", ...]` and `&Vec<String>` alike.
+    pub async fn fetch_stop_departures<S: AsRef<str>>(&self, stop_ids: &[S]) -> Result<Vec<Departure>> {
+        let stop_ids_param = stop_ids.iter().map(AsRef::as_ref).collect::<Vec<_>>().join(",");
 
         let response = self
-            .client
-            .get(format!("{}/v4/public/stop_departures", TRANSIT_API_BASE))
-            .header("apiKey", &self.api_key)
-            .query(&[
-                ("global_stop_ids", stop_ids_param.as_str()),
-                ("should_update_realtime", "true"),
-                ("max_num_departures", "10"),
-            ])
-            .send()
-            .await
-            .context("Failed to call stop_departures")?;
+            .send_with_retry("stop_departures", || {
+                self.client
+                    .get(format!("{}/v4/public/stop_departures", self.base_url))
+                    .header("apiKey", &self.api_key)
+                    .query(&[
+                        ("global_stop_ids", stop_ids_param.as_str()),
+                        ("should_update_realtime", "true"),
+                        ("max_num_departures", "10"),
+                    ])
+            })
+            .await?;
 
         if !response.status().is_success() {
             anyhow::bail!("stop_departures returned {}", response.status());
         }
 
-        let body: StopDeparturesResponse = response
-            .json()
+        let body = response
+            .bytes()
             .await
-            .context("Failed to parse stop_departures response")?;
-
-        let mut departures = Vec::new();
-
-        for route_dep in body.route_departures {
-            for merged in route_dep.merged_itineraries {
-                let headsign = merged
-                    .itineraries
-                    .first()
-                    .and_then(|i| i.merged_headsign.clone().or_else(|| i.headsign.clone()));
-
-                for item in merged.schedule_items {
-                    let scheduled = item.scheduled_departure_time.unwrap_or(item.departure_time);
-                    let delay_seconds = item
-                        .is_real_time
-                        .then(|| (item.departure_time - scheduled) as i32);
-
-                    departures.push(Departure {
-                        global_stop_id: route_dep.global_stop_id.clone(),
-                        global_route_id: route_dep.global_route_id.clone(),
-                        route_short_name: route_dep.route_short_name.clone(),
-                        headsign: headsign.clone(),
-                        departure_time: item.departure_time,
-                        scheduled_departure_time: scheduled,
-                        delay_seconds,
-                        is_real_time: item.is_real_time,
-                        is_cancelled: item.is_cancelled,
-                        rt_trip_id: item.rt_trip_id,
-                    });
-                }
+            .context("Failed to read stop_departures response")?;
+        if let Some(archive) = &self.archive {
+            // A full disk shouldn't cost us the poll itself
+            if let Err(e) = archive.save("stop_departures", &body).await {
+                tracing::warn!(error = %e, "Failed to archive stop_departures response");
             }
         }
+        parse_stop_departures_with(&body, &self.parse_options)
+    }
 
-        departures.sort_by_key(|d| d.departure_time);
+    /// Poll a batch of stops, keeping only departures on routes accepted by `routes`.
+    pub async fn fetch_stop_departures_matching<S: AsRef<str>>(
+        &self,
+        stop_ids: &[S],
+        routes: &RouteMatcher,
+    ) -> Result<Vec<Departure>> {
+        self.fetch_stop_departures_filtered(stop_ids, &DepartureFilter::routes(routes.clone()))
+            .await
+    }
+
+    /// Poll a batch of stops, keeping only departures `filter` accepts.
+    pub async fn fetch_stop_departures_filtered<S: AsRef<str>>(
+        &self,
+        stop_ids: &[S],
+        filter: &DepartureFilter,
+    ) -> Result<Vec<Departure>> {
+        let mut departures = self.fetch_stop_departures(stop_ids).await?;
+        departures.retain(|d| filter.matches(d));
         Ok(departures)
     }
 
+    /// Departure board for one stop: the next `limit` departures across all
+    /// routes, soonest first. Cancelled and already-departed trips are dropped.
+    pub async fn next_departures(&self, stop_id: &str, limit: usize) -> Result<Vec<BoardEntry>> {
+        let now = FeedTime::now();
+        let departures = self.fetch_stop_departures(&[stop_id]).await?;
+
+        // fetch_stop_departures already sorts by departure_time
+        Ok(departures
+            .into_iter()
+            .filter(|d| d.global_stop_id == stop_id)
+            .filter_map(|d| d.minutes_until(now).filter(|m| *m >= 0.0).map(|m| (d, m)))
+            .take(limit)
+            .map(|(d, minutes_away)| BoardEntry {
+                minutes_away,
+                global_route_id: d.global_route_id,
+                route_short_name: d.route_short_name,
+                headsign: d.headsign,
+                departure_time: d.departure_time,
+                delay_seconds: d.delay_seconds,
+                is_real_time: d.is_real_time,
+            })
+            .collect())
+    }
+
     /// One-time: log all route IDs near UCLA to find BBB global_route_ids.
     pub async fn discover_route_id(&self) -> Result<()> {
         let response = self
-            .client
-            .get(format!("{}/v4/public/nearby_routes", TRANSIT_API_BASE))
-            .header("apiKey", &self.api_key)
-            .query(&[
-                ("lat", DISCOVERY_LAT.to_string()),
-                ("lon", DISCOVERY_LON.to_string()),
-                ("max_distance", "300".to_string()),
-                ("should_update_realtime", "false".to_string()),
-                ("max_num_departures", "0".to_string()),
-            ])
-            .send()
-            .await
-            .context("Failed to call nearby_routes")?;
+            .send_with_retry("nearby_routes", || {
+                self.client
+                    .get(format!("{}/v4/public/nearby_routes", self.base_url))
+                    .header("apiKey", &self.api_key)
+                    .query(&[
+                        ("lat", DISCOVERY_LAT.to_string()),
+                        ("lon", DISCOVERY_LON.to_string()),
+                        ("max_distance", "300".to_string()),
+                        ("should_update_realtime", "false".to_string()),
+                        ("max_num_departures", "0".to_string()),
+                    ])
+            })
+            .await?;
 
         if !response.status().is_success() {
             anyhow::bail!("Transit API returned {}", response.status());
@@ -261,17 +748,17 @@ impl TransitClient {
     /// One-time: find stop IDs near a coordinate for populating ROUTE_IDS.
     pub async fn resolve_stops(&self, lat: f64, lon: f64) -> Result<Vec<NearbyStop>> {
         let response = self
-            .client
-            .get(format!("{}/v4/public/nearby_stops", TRANSIT_API_BASE))
-            .header("apiKey", &self.api_key)
-            .query(&[
-                ("lat", lat.to_string()),
-                ("lon", lon.to_string()),
-                ("max_distance", "200".to_string()),
-            ])
-            .send()
-            .await
-            .context("Failed to call nearby_stops")?;
+            .send_with_retry("nearby_stops", || {
+                self.client
+                    .get(format!("{}/v4/public/nearby_stops", self.base_url))
+                    .header("apiKey", &self.api_key)
+                    .query(&[
+                        ("lat", lat.to_string()),
+                        ("lon", lon.to_string()),
+                        ("max_distance", "200".to_string()),
+                    ])
+            })
+            .await?;
 
         if !response.status().is_success() {
             anyhow::bail!("nearby_stops returned {}", response.status());
@@ -1,69 +1,182 @@
 use anyhow::Result;
 use axum::{
-    extract::{Query, State},
-    http::{header, StatusCode},
+    extract::{Path, Query, State},
+    http::{header, HeaderValue, StatusCode},
     response::{Html, IntoResponse, Json},
     routing::{get, post},
     Router,
 };
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tower_http::cors::{Any, CorsLayer};
 
 use crate::{
     api::TransitClient,
     db::Database,
-    models::{PollResult, Stop},
-    poll_once,
+    filter::{route_id_mapping, DepartureFilter, PollTargets, RouteMatcher, RouteNormalization},
+    history::PollHistory,
+    models::{FeedTime, Stop},
+    poll_once, snapshot,
+    wire::{WireDeparture, WirePollResult, WireSnapshot, WireStop},
 };
 
-pub type Cache = Arc<RwLock<Option<PollResult>>>;
+pub type Cache = Arc<RwLock<PollHistory>>;
 
 #[derive(Clone)]
-struct AppState {
-    cache: Cache,
-    stops: Arc<Vec<Stop>>,
-    client: Arc<TransitClient>,
-    db: Arc<Database>,
-    stop_ids: Arc<Vec<String>>,
-    chunks_per_poll: usize,
+pub struct AppState {
+    pub cache: Cache,
+    pub stops: Arc<RwLock<Vec<Stop>>>,
+    pub client: Arc<TransitClient>,
+    pub db: Arc<Database>,
+    /// What the poll loop collects; /api/refresh polls the same
+    pub targets: Arc<RwLock<PollTargets>>,
+    /// Where to keep a binary copy of the latest poll for fast restarts
+    pub snapshot_path: Option<Arc<PathBuf>>,
+    /// How often the poll loop runs; drives Cache-Control on /api/snapshot
+    pub poll_interval: Duration,
+    /// Applied to route IDs in exact route matching; a no-op by default
+    pub route_normalization: Arc<RouteNormalization>,
 }
 
 #[derive(serde::Deserialize)]
 struct DepartureParams {
     stop_ids: Option<String>,
+    routes: Option<String>,
+    /// `exact` (default), `prefix`, or `regex`
+    route_match: Option<String>,
+    headsigns: Option<String>,
+    trip_ids: Option<String>,
 }
 
-/// GET /api/departures?stop_ids=BBB:1234,BBB:5678
+fn split_list(list: Option<&str>) -> Vec<String> {
+    list.map(|l| l.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+fn route_matcher(
+    params: &DepartureParams,
+    normalization: &RouteNormalization,
+) -> Result<Option<RouteMatcher>, StatusCode> {
+    let Some(routes) = &params.routes else { return Ok(None) };
+    let list = || routes.split(',').map(|s| s.trim().to_string()).collect();
+    match params.route_match.as_deref().unwrap_or("exact") {
+        "exact" => Ok(Some(RouteMatcher::exact(list(), normalization))),
+        "prefix" => Ok(Some(RouteMatcher::Prefix(list()))),
+        "regex" => regex::Regex::new(routes)
+            .map(|re| Some(RouteMatcher::Pattern(re)))
+            .map_err(|_| StatusCode::BAD_REQUEST),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+/// GET /api/departures?stop_ids=BBB:1234,BBB:5678&routes=1&route_match=prefix&headsigns=UCLA&trip_ids=…
 async fn get_departures(
     State(state): State<AppState>,
     Query(params): Query<DepartureParams>,
-) -> Result<Json<PollResult>, StatusCode> {
+) -> Result<Json<WirePollResult>, StatusCode> {
+    let filter = DepartureFilter {
+        stop_ids: split_list(params.stop_ids.as_deref()),
+        routes: route_matcher(&params, &state.route_normalization)?,
+        headsigns: split_list(params.headsigns.as_deref()),
+        trip_ids: split_list(params.trip_ids.as_deref()),
+    };
     let cache = state.cache.read().await;
-    let poll = cache.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
-
-    match &params.stop_ids {
-        Some(ids_str) => {
-            let filter: HashSet<&str> = ids_str.split(',').map(|s| s.trim()).collect();
-            let filtered: Vec<_> = poll
-                .departures
-                .iter()
-                .filter(|d| filter.contains(d.global_stop_id.as_str()))
-                .cloned()
-                .collect();
-            Ok(Json(PollResult {
-                polled_at: poll.polled_at,
-                departures: filtered,
-            }))
-        }
-        None => Ok(Json(poll.clone())),
+    let poll = cache.latest().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    if filter.is_empty() {
+        return Ok(Json(WirePollResult::from(poll)));
     }
+
+    let filtered: Vec<_> = poll
+        .departures
+        .iter()
+        .filter(|d| filter.matches(d))
+        .map(WireDeparture::from)
+        .collect();
+    Ok(Json(WirePollResult {
+        polled_at: poll.polled_at.secs(),
+        departures: filtered,
+    }))
+}
+
+/// GET /api/departures/:route — latest poll for one route, by short name
+/// (`1`) or global route ID (`BBB:14412`)
+async fn get_route_departures(
+    State(state): State<AppState>,
+    Path(route): Path<String>,
+) -> Result<Json<WirePollResult>, (StatusCode, &'static str)> {
+    let cache = state.cache.read().await;
+    let poll = cache
+        .latest()
+        .ok_or((StatusCode::SERVICE_UNAVAILABLE, "No poll has completed yet"))?;
+    let matcher = RouteMatcher::exact(vec![route.clone()], &state.route_normalization);
+    Ok(Json(WirePollResult {
+        polled_at: poll.polled_at.secs(),
+        departures: poll
+            .departures
+            .iter()
+            .filter(|d| matcher.matches_departure(d) || d.global_route_id == route)
+            .map(WireDeparture::from)
+            .collect(),
+    }))
+}
+
+/// GET /api/route-ids — each normalized route short name in the latest poll
+/// with the raw names that map to it, to audit the configured normalization
+async fn get_route_ids(
+    State(state): State<AppState>,
+) -> Result<Json<BTreeMap<String, BTreeSet<String>>>, StatusCode> {
+    let cache = state.cache.read().await;
+    let poll = cache.latest().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let names = poll.departures.iter().map(|d| d.route_short_name.as_str());
+    Ok(Json(route_id_mapping(names, &state.route_normalization)))
+}
+
+#[derive(serde::Deserialize)]
+struct NextParams {
+    stop_id: String,
+    limit: Option<usize>,
+}
+
+/// GET /api/next?stop_id=BBB:7023&limit=5 — upcoming departures at one stop
+/// across all routes, soonest first, from the latest poll
+async fn get_next(
+    State(state): State<AppState>,
+    Query(params): Query<NextParams>,
+) -> Result<Json<Vec<WireDeparture>>, StatusCode> {
+    let cache = state.cache.read().await;
+    let poll = cache.latest().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(Json(
+        poll.upcoming_at(&params.stop_id, FeedTime::now())
+            .into_iter()
+            .take(params.limit.unwrap_or(10))
+            .map(WireDeparture::from)
+            .collect(),
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct HistoryParams {
+    n: Option<usize>,
+}
+
+/// GET /api/history?n=5 — most recent polls held in memory, newest first
+async fn get_history(
+    State(state): State<AppState>,
+    Query(params): Query<HistoryParams>,
+) -> Json<Vec<WirePollResult>> {
+    let cache = state.cache.read().await;
+    let n = params.n.unwrap_or(cache.len());
+    Json(cache.recent(n).into_iter().map(WirePollResult::from).collect())
 }
 
 /// GET /api/stops — static stop list with coordinates
-async fn get_stops(State(state): State<AppState>) -> Json<Vec<Stop>> {
-    Json((*state.stops).clone())
+async fn get_stops(State(state): State<AppState>) -> Json<Vec<WireStop>> {
+    Json(state.stops.read().await.iter().map(WireStop::from).collect())
 }
 
 /// GET /api/status
@@ -71,13 +184,38 @@ async fn get_status(State(state): State<AppState>) -> Json<serde_json::Value> {
     let cache = state.cache.read().await;
     Json(serde_json::json!({
         "status": "ok",
-        "last_polled_at": cache.as_ref().map(|p| p.polled_at),
-        "departure_count": cache.as_ref().map(|p| p.departures.len()),
-        "stop_count": state.stops.len(),
+        "last_polled_at": cache.latest().map(|p| p.polled_at),
+        "departure_count": cache.latest().map(|p| p.departures.len()),
+        "realtime_coverage": cache.latest().map(|p| p.realtime_coverage()),
+        "history_len": cache.len(),
+        "stale": cache.is_stale(),
+        "stop_count": state.stops.read().await.len(),
         "timestamp": chrono::Utc::now().timestamp(),
     }))
 }
 
+/// GET /api/snapshot — stops + latest poll in one payload, cacheable until
+/// the next poll is due
+async fn get_snapshot(State(state): State<AppState>) -> impl IntoResponse {
+    let cache = state.cache.read().await;
+    let now = FeedTime::now();
+    let interval = state.poll_interval.as_secs() as i64;
+    let max_age = cache
+        .latest()
+        .map(|p| (interval - p.polled_at.age(now).num_seconds()).clamp(0, interval))
+        .unwrap_or(0);
+
+    let body = WireSnapshot {
+        generated_at: now.secs(),
+        stale: cache.is_stale(),
+        stops: state.stops.read().await.iter().map(WireStop::from).collect(),
+        poll: cache.latest().map(WirePollResult::from),
+    };
+    let cache_control = HeaderValue::from_str(&format!("public, max-age={}", max_age))
+        .expect("max-age header is ASCII");
+    ([(header::CACHE_CONTROL, cache_control)], Json(body))
+}
+
 /// GET /api/quota — API call counts derived from departure_log
 async fn get_quota(State(state): State<AppState>) -> Result<Json<serde_json::Value>, StatusCode> {
     let (total_polls, today_polls) = state
@@ -85,7 +223,7 @@ async fn get_quota(State(state): State<AppState>) -> Result<Json<serde_json::Val
         .count_polls()
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let c = state.chunks_per_poll as i64;
+    let c = state.targets.read().await.stop_ids.chunks(100).count() as i64;
     Ok(Json(serde_json::json!({
         "total_polls": total_polls,
         "today_polls": today_polls,
@@ -98,16 +236,32 @@ async fn get_quota(State(state): State<AppState>) -> Result<Json<serde_json::Val
 /// POST /api/refresh — force an immediate poll regardless of time window
 async fn post_refresh(
     State(state): State<AppState>,
-) -> Result<Json<PollResult>, StatusCode> {
-    match poll_once(&state.client, &state.db, &state.stop_ids, false).await {
+) -> Result<Json<WirePollResult>, StatusCode> {
+    let targets = state.targets.read().await.clone();
+    match poll_once(state.client.as_ref(), &state.db, &targets, false).await {
         Some(result) => {
-            *state.cache.write().await = Some(result.clone());
-            Ok(Json(result))
+            let body = WirePollResult::from(&result);
+            if let Some(path) = &state.snapshot_path {
+                if let Err(e) = snapshot::save(path, &result) {
+                    tracing::warn!(error = %e, "Failed to write snapshot cache");
+                }
+            }
+            state.cache.write().await.push(result);
+            Ok(Json(body))
         }
         None => Err(StatusCode::BAD_GATEWAY),
     }
 }
 
+/// GET /metrics — Prometheus text format
+#[cfg(feature = "metrics")]
+async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::metrics::render(),
+    )
+}
+
 /// GET / — departure map
 async fn get_map() -> impl IntoResponse {
     (
@@ -118,15 +272,9 @@ async fn get_map() -> impl IntoResponse {
 
 pub async fn run_server(
     addr: &str,
-    cache: Cache,
-    stops: Arc<Vec<Stop>>,
-    client: Arc<TransitClient>,
-    db: Arc<Database>,
-    stop_ids: Arc<Vec<String>>,
-    chunks_per_poll: usize,
+    state: AppState,
+    shutdown: impl Future<Output = ()> + Send + 'static,
 ) -> Result<()> {
-    let state = AppState { cache, stops, client, db, stop_ids, chunks_per_poll };
-
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
@@ -135,18 +283,26 @@ pub async fn run_server(
     let app = Router::new()
         .route("/", get(get_map))
         .route("/api/departures", get(get_departures))
+        .route("/api/departures/:route", get(get_route_departures))
+        .route("/api/route-ids", get(get_route_ids))
+        .route("/api/next", get(get_next))
+        .route("/api/history", get(get_history))
         .route("/api/stops", get(get_stops))
         .route("/api/status", get(get_status))
+        .route("/api/snapshot", get(get_snapshot))
         .route("/api/quota", get(get_quota))
-        .route("/api/refresh", post(post_refresh))
+        .route("/api/refresh", post(post_refresh));
+    #[cfg(feature = "metrics")]
+    let app = app.route("/metrics", get(get_metrics));
+    let app = app
         .with_state(state)
         .layer(cors);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
     tracing::info!("Listening on http://{}", addr);
-    tracing::info!("GET /  GET /api/departures  GET /api/stops  GET /api/status  GET /api/quota  POST /api/refresh");
+    tracing::info!("GET /  GET /api/departures[/:route]  GET /api/next  GET /api/history  GET /api/stops  GET /api/status  GET /api/snapshot  GET /api/quota  POST /api/refresh");
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app).with_graceful_shutdown(shutdown).await?;
     Ok(())
 }
 
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::models::FeedTime;
+
+pub const DEFAULT_ARCHIVE_RETENTION_DAYS: i64 = 7;
+
+/// Raw API responses saved to disk before parsing, so a poll that produced
+/// something odd can be re-run byte for byte with `--replay <dir>`.
+///
+/// Files are named `<endpoint>-<unix millis>.json`, which sorts in fetch order.
+#[derive(Debug, Clone)]
+pub struct FeedArchive {
+    dir: PathBuf,
+}
+
+impl FeedArchive {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write one response body, creating the directory if needed.
+    pub async fn save(&self, endpoint: &str, body: &[u8]) -> Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let millis = chrono::Utc::now().timestamp_millis();
+        let path = self.dir.join(format!("{}-{}.json", endpoint, millis));
+        tokio::fs::write(&path, body)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Delete archived files whose name stamp is more than `days` old. Files
+    /// not named by `save` are left alone. Returns how many were removed.
+    pub async fn prune_keeping_days(&self, days: i64) -> Result<usize> {
+        let cutoff_millis = (FeedTime::now().secs() - days * 86_400) * 1000;
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", self.dir.display())),
+        };
+
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await.context("Failed to list archive")? {
+            let path = entry.path();
+            let Some(millis) = archived_at(&path) else { continue };
+            if millis < cutoff_millis {
+                tokio::fs::remove_file(&path)
+                    .await
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            tracing::info!(removed, dir = %self.dir.display(), "Pruned archived responses");
+        }
+        Ok(removed)
+    }
+}
+
+/// The millisecond stamp in a `<endpoint>-<millis>.json` file name, i.e.
+/// when `FeedArchive::save` fetched it.
+pub fn archived_at(path: &Path) -> Option<i64> {
+    if path.extension()? != "json" {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    stem.rsplit_once('-')?.1.parse().ok()
+}
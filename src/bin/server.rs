@@ -1,14 +1,152 @@
 use anyhow::{Context, Result};
-use bigbluebunch::{api::TransitClient, api_server, db::Database, poll_once};
+use bigbluebunch::{
+    analysis::{delay_summary_by_trip, trips_exceeding_delay},
+    archive::{FeedArchive, DEFAULT_ARCHIVE_RETENTION_DAYS},
+    api::{RetryPolicy, TransitClient},
+    api_server,
+    breaker::{CircuitBreaker, DEFAULT_FAILURE_THRESHOLD, DEFAULT_OPEN_INTERVAL},
+    bunching::DEFAULT_BUNCHING_THRESHOLD_SECS,
+    db::{BufferedWriter, Database, DEFAULT_WRITE_BUFFER_AGE, DEFAULT_WRITE_BUFFER_ROWS},
+    filter::{PollTargets, RouteNormalization},
+    history::{PollHistory, DEFAULT_HISTORY_SIZE},
+    init_tracing,
+    models::{FeedTime, Stop},
+    notify::{detect_events, EventTracker, MonitorEvent, Notifier, WebhookNotifier},
+    fetch_poll, snapshot, LogFormat,
+};
+use clap::Parser;
 use chrono::{Datelike, Timelike, Utc, Weekday};
 use chrono_tz::US::Pacific;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::{watch, RwLock};
 
 // 15 min → 24 polls/day × 2 calls/poll × 22 weekdays = ~1056 calls/month
-const POLL_INTERVAL_SECS: u64 = 900;
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 900;
+// Each poll makes one call per 100 stops, 13 s apart; anything tighter than
+// this overlaps polls and burns through the monthly quota.
+const MIN_POLL_INTERVAL_SECS: u64 = 60;
+const DEFAULT_LATE_THRESHOLD_SECS: i32 = 600;
+
+/// Polls Transit App departures into Postgres and serves them over HTTP.
+///
+/// Precedence: flags, then environment (and .env), then the config file,
+/// then built-in defaults.
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// TOML config file
+    #[arg(long, env = "BBB_CONFIG", default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+
+    /// Comma-separated global route IDs; replaces ROUTE_IDS (ROUTE_IDS_FILE is still merged in)
+    #[arg(long, value_delimiter = ',')]
+    routes: Option<Vec<String>>,
+
+    /// Seconds between polls, aligned to wall-clock boundaries [default: 900]
+    #[arg(
+        long,
+        env = "POLL_INTERVAL_SECS",
+        value_parser = clap::value_parser!(u64).range(MIN_POLL_INTERVAL_SECS..),
+    )]
+    interval_secs: Option<u64>,
+
+    /// Postgres connection string
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    database_url: Option<String>,
+
+    /// API server port [default: 8080]
+    #[arg(long, env = "PORT")]
+    port: Option<u16>,
+
+    /// Log output: pretty or json
+    #[arg(long, env = "LOG_FORMAT", default_value = "pretty")]
+    log_format: LogFormat,
+}
+
+const DEFAULT_CONFIG_PATH: &str = "bigbluebunch.toml";
+
+/// Contents of `bigbluebunch.toml`. Every key is optional; unknown keys are
+/// an error so typos don't silently fall back to defaults.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    api_base: Option<String>,
+    /// Per-request timeout for Transit API calls
+    timeout_secs: Option<u64>,
+    routes: Option<Vec<String>>,
+    interval_secs: Option<u64>,
+    database_url: Option<String>,
+    port: Option<u16>,
+    retry: Option<RetryConfig>,
+    /// Extra headers sent on every Transit API request
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Extra query parameters added to every Transit API request
+    #[serde(default)]
+    query: BTreeMap<String, String>,
+    /// How route IDs are normalized before exact matching in the API
+    #[serde(default)]
+    route_normalization: RouteNormalization,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetryConfig {
+    max_attempts: u32,
+    base_delay_ms: u64,
+}
+
+impl FileConfig {
+    /// A missing file at the default path is fine; a missing file the user
+    /// asked for is not.
+    fn load(path: &Path, explicit: bool) -> Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
+
+/// Send `events` to every notifier in the background, so a slow webhook
+/// can't hold up the next poll.
+fn dispatch(notifiers: &Arc<Vec<WebhookNotifier>>, events: Vec<MonitorEvent>) {
+    if notifiers.is_empty() || events.is_empty() {
+        return;
+    }
+    let notifiers = Arc::clone(notifiers);
+    tokio::spawn(async move {
+        for event in &events {
+            for notifier in notifiers.iter() {
+                if let Err(e) = notifier.notify(event).await {
+                    tracing::warn!(error = %e, event = %event, "Failed to send notification");
+                }
+            }
+        }
+    });
+}
+
+/// After a failed poll, raise `StaleFeed` once polls have been failing for
+/// more than two intervals. Measured from the first failure rather than the
+/// last success, so the overnight gap between active windows doesn't count.
+fn notify_if_stale(
+    notifiers: &Arc<Vec<WebhookNotifier>>,
+    tracker: &mut EventTracker,
+    last_poll: Option<FeedTime>,
+    failing_since: FeedTime,
+    poll_interval_secs: u64,
+) {
+    let failing_secs = FeedTime::now().secs() - failing_since.secs();
+    if failing_secs > 2 * poll_interval_secs as i64 {
+        dispatch(notifiers, tracker.transitions(vec![MonitorEvent::StaleFeed { last_poll, failing_secs }]));
+    }
+}
 
 fn is_active_window() -> bool {
     let now = Utc::now().with_timezone(&Pacific);
@@ -18,175 +156,655 @@ fn is_active_window() -> bool {
     }
     let h = now.hour();
     let m = now.minute();
-    (h >= 8 && h < 11) || (h == 11 && m == 0) || (h >= 15 && h < 18) || (h == 18 && m == 0)
+    (8..11).contains(&h) || (h == 11 && m == 0) || (15..18).contains(&h) || (h == 18 && m == 0)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .init();
-
-    match dotenvy::dotenv() {
-        Ok(path) => eprintln!(".env loaded from {:?}", path),
-        Err(e) => eprintln!(".env not found: {}", e),
-    }
-
-    tracing::info!("Big Blue Bus Tracker starting");
+/// Which routes and watch-point stops to poll, read from the environment.
+#[derive(Debug, Clone)]
+struct RouteConfig {
+    /// Routes for full stop bootstrap — all stops on these routes go into the stops table
+    route_ids: Vec<String>,
+    /// Routes used only to resolve stop metadata for EXTRA_STOP_IDS.
+    /// All stops on these routes are fetched but only those matching EXTRA_STOP_IDS are kept.
+    extra_route_ids: Vec<String>,
+    /// Specific stop IDs to watch — must overlap with stops seeded by ROUTE_IDS or EXTRA_ROUTE_IDS
+    extra_stop_ids: HashSet<String>,
+}
 
-    // Routes for full stop bootstrap — all stops on these routes go into the stops table
-    let route_ids: Vec<String> = std::env::var("ROUTE_IDS")
+fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
         .unwrap_or_default()
         .split(',')
         .map(|s| s.trim().to_string())
         .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// One route ID per line; blank lines and `#` comments are ignored.
+fn read_route_file(path: &str) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("ROUTE_IDS_FILE {} could not be read", path))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect())
+}
+
+impl RouteConfig {
+    /// `routes` (from --routes) takes the place of ROUTE_IDS when given;
+    /// `file_routes` (from the config file) is used when neither is set.
+    fn from_env(routes: Option<&[String]>, file_routes: &[String]) -> Result<Self> {
+        // ROUTE_IDS and ROUTE_IDS_FILE are unioned, keeping first-seen order
+        let mut route_ids = match routes {
+            Some(routes) => routes.to_vec(),
+            None => env_list("ROUTE_IDS"),
+        };
+        if route_ids.is_empty() {
+            route_ids = file_routes.to_vec();
+        }
+        if let Ok(path) = std::env::var("ROUTE_IDS_FILE") {
+            for id in read_route_file(&path)? {
+                if !route_ids.contains(&id) {
+                    route_ids.push(id);
+                }
+            }
+        }
+        if route_ids.is_empty() {
+            anyhow::bail!(
+                "No routes configured: pass --routes or set ROUTE_IDS / ROUTE_IDS_FILE in .env\n\
+                 Run: cargo run -- --discover   to find route IDs near UCLA"
+            );
+        }
+        Ok(Self {
+            route_ids,
+            extra_route_ids: env_list("EXTRA_ROUTE_IDS"),
+            extra_stop_ids: env_list("EXTRA_STOP_IDS").into_iter().collect(),
+        })
+    }
+}
+
+/// Fetch every stop on `route_ids` into the stops table, 13 s apart.
+async fn bootstrap_routes(client: &TransitClient, db: &Database, route_ids: &[String]) -> Result<()> {
+    tracing::info!(routes = route_ids.len(), "Bootstrapping stops from route_details");
+    for (i, route_id) in route_ids.iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(Duration::from_secs(13)).await;
+        }
+        match client.fetch_route_stops(route_id).await {
+            Ok(stops) => {
+                tracing::info!(route = %route_id, count = stops.len(), "Fetched stops");
+                db.upsert_route_stops(route_id, &stops).await?;
+            }
+            Err(e) => {
+                tracing::error!(route = %route_id, error = %e, "Failed to fetch stops");
+            }
+        }
+    }
+    tokio::time::sleep(Duration::from_secs(13)).await;
+    Ok(())
+}
+
+/// Extra route bootstrap (metadata only, filtered to EXTRA_STOP_IDS).
+/// Only calls the API when some EXTRA_STOP_IDS are missing from the stops table.
+async fn bootstrap_extra_stops(client: &TransitClient, db: &Database, config: &RouteConfig) -> Result<()> {
+    if config.extra_route_ids.is_empty() || config.extra_stop_ids.is_empty() {
+        return Ok(());
+    }
+
+    let in_db: HashSet<String> = db.get_all_stop_ids().await?.into_iter().collect();
+    let missing: HashSet<&String> = config
+        .extra_stop_ids
+        .iter()
+        .filter(|id| !in_db.contains(*id))
         .collect();
 
-    if route_ids.is_empty() {
-        anyhow::bail!(
-            "ROUTE_IDS must be set in .env\n\
-             Run: cargo run -- --discover   to find route IDs near UCLA"
-        );
+    if missing.is_empty() {
+        return Ok(());
     }
 
-    // Routes used only to resolve stop metadata for EXTRA_STOP_IDS.
-    // All stops on these routes are fetched but only those matching EXTRA_STOP_IDS are kept.
-    let extra_route_ids: Vec<String> = std::env::var("EXTRA_ROUTE_IDS")
-        .unwrap_or_default()
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
+    tracing::info!(count = missing.len(), "Bootstrapping extra stop metadata from EXTRA_ROUTE_IDS");
+    for (i, route_id) in config.extra_route_ids.iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(Duration::from_secs(13)).await;
+        }
+        match client.fetch_route_stops(route_id).await {
+            Ok(stops) => {
+                let filtered: Vec<_> = stops.into_iter()
+                    .filter(|s| missing.contains(&s.global_stop_id))
+                    .collect();
+                tracing::info!(route = %route_id, count = filtered.len(), "Upserted extra stops");
+                if !filtered.is_empty() {
+                    db.upsert_stops(&filtered).await?;
+                }
+            }
+            Err(e) => {
+                tracing::error!(route = %route_id, error = %e, "Failed to fetch extra route stops");
+            }
+        }
+    }
+    tokio::time::sleep(Duration::from_secs(13)).await;
+    Ok(())
+}
+
+/// What to poll for `config`: every stop on its routes plus the watch-point
+/// stops that made it into the stops table, keeping only the configured
+/// routes' departures outside the watch points. Also returns those stops'
+/// metadata for the map.
+async fn poll_targets(db: &Database, config: &RouteConfig) -> Result<(PollTargets, Vec<Stop>)> {
+    let mut stop_ids = db.stop_ids_for_routes(&config.route_ids).await?;
+    let all_stops = db.get_all_stops().await?;
+    let watch_stop_ids: HashSet<String> = all_stops
+        .iter()
+        .map(|s| &s.global_stop_id)
+        .filter(|id| config.extra_stop_ids.contains(*id))
+        .cloned()
         .collect();
+    for id in &watch_stop_ids {
+        if !stop_ids.contains(id) {
+            stop_ids.push(id.clone());
+        }
+    }
+    stop_ids.sort();
 
-    // Specific stop IDs to watch — must overlap with stops seeded by ROUTE_IDS or EXTRA_ROUTE_IDS
-    let extra_stop_ids: HashSet<String> = std::env::var("EXTRA_STOP_IDS")
-        .unwrap_or_default()
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
+    let polled: HashSet<&String> = stop_ids.iter().collect();
+    let stops = all_stops.into_iter().filter(|s| polled.contains(&s.global_stop_id)).collect();
+    let targets = PollTargets {
+        stop_ids,
+        route_ids: config.route_ids.iter().cloned().collect(),
+        watch_stop_ids,
+    };
+    Ok((targets, stops))
+}
+
+/// Re-read .env and the config file, bootstrap any newly added routes and
+/// watch-point stops, and work out the new poll targets.
+///
+/// Returns the new config and targets on success; on any error the caller
+/// keeps `current`. Only the route settings are reloaded.
+async fn reload_routes(
+    client: &TransitClient,
+    db: &Database,
+    current: &RouteConfig,
+    cli_routes: Option<&[String]>,
+    config_path: &Path,
+    explicit_config: bool,
+) -> Result<(RouteConfig, PollTargets, Vec<Stop>)> {
+    // Under docker-compose the settings come from env_file: and there's no
+    // .env to read, so the process environment stands as it is
+    match dotenvy::dotenv_override() {
+        Ok(_) => {}
+        Err(e) if e.not_found() => tracing::debug!("No .env to re-read"),
+        Err(e) => return Err(e).context("Failed to re-read .env"),
+    }
+    let file = FileConfig::load(config_path, explicit_config)?;
+    let next = RouteConfig::from_env(cli_routes, file.routes.as_deref().unwrap_or_default())?;
+
+    let bootstrapped = db.bootstrapped_routes().await?;
+    let added: Vec<String> = next
+        .route_ids
+        .iter()
+        .filter(|r| !bootstrapped.contains(*r))
+        .cloned()
+        .collect();
+    let removed: Vec<&String> = current
+        .route_ids
+        .iter()
+        .filter(|r| !next.route_ids.contains(r))
         .collect();
 
-    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
-    let addr = format!("0.0.0.0:{}", port);
+    tracing::info!(
+        old = ?current.route_ids,
+        new = ?next.route_ids,
+        "SIGHUP: reloading routes"
+    );
+    if !removed.is_empty() {
+        tracing::info!(?removed, "Removed routes are no longer polled");
+    }
 
-    let database_url = std::env::var("DATABASE_URL")
-        .context("DATABASE_URL must be set in .env")?;
+    if !added.is_empty() {
+        bootstrap_routes(client, db, &added).await?;
+    }
+    bootstrap_extra_stops(client, db, &next).await?;
 
-    let client = Arc::new(TransitClient::from_env());
-    let db = Arc::new(Database::new(&database_url).await?);
-    let cache: api_server::Cache = Arc::new(RwLock::new(None));
-
-    // ── Full route bootstrap (runs once, when stops table is empty) ──────────
-    if !db.stops_initialized().await? {
-        tracing::info!(routes = route_ids.len(), "Bootstrapping stops from route_details");
-        for (i, route_id) in route_ids.iter().enumerate() {
-            if i > 0 {
-                tokio::time::sleep(Duration::from_secs(13)).await;
-            }
-            match client.fetch_route_stops(route_id).await {
-                Ok(stops) => {
-                    tracing::info!(route = %route_id, count = stops.len(), "Fetched stops");
-                    db.upsert_stops(&stops).await?;
-                }
-                Err(e) => {
-                    tracing::error!(route = %route_id, error = %e, "Failed to fetch stops");
-                }
-            }
+    let (targets, stops) = poll_targets(db, &next).await?;
+    if targets.stop_ids.is_empty() {
+        anyhow::bail!("No stops to poll for routes {:?}", next.route_ids);
+    }
+    Ok((next, targets, stops))
+}
+
+/// Resolves on the first Ctrl-C or SIGTERM (what systemd sends on stop).
+/// After that, a second signal exits immediately in case draining hangs.
+async fn shutdown_signal(mut terminate: Signal) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "Failed to listen for Ctrl-C");
+            std::future::pending::<()>().await;
         }
-        tokio::time::sleep(Duration::from_secs(13)).await;
+    };
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate.recv() => {}
     }
+    tracing::info!("Shutdown requested — signal again to force exit");
 
-    // ── Extra route bootstrap (metadata only, filtered to EXTRA_STOP_IDS) ───
-    // Runs whenever any EXTRA_STOP_IDS are missing from the stops table.
-    if !extra_route_ids.is_empty() && !extra_stop_ids.is_empty() {
-        let in_db: HashSet<String> = db.get_all_stop_ids().await?.into_iter().collect();
-        let missing: HashSet<&String> = extra_stop_ids.iter().filter(|id| !in_db.contains(*id)).collect();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+        tracing::warn!("Second shutdown signal, exiting without draining");
+        std::process::exit(130);
+    });
+}
 
-        if !missing.is_empty() {
-            tracing::info!(count = missing.len(), "Bootstrapping extra stop metadata from EXTRA_ROUTE_IDS");
-            for (i, route_id) in extra_route_ids.iter().enumerate() {
-                if i > 0 {
-                    tokio::time::sleep(Duration::from_secs(13)).await;
-                }
-                match client.fetch_route_stops(route_id).await {
-                    Ok(stops) => {
-                        let filtered: Vec<_> = stops.into_iter()
-                            .filter(|s| missing.contains(&s.global_stop_id))
-                            .collect();
-                        tracing::info!(route = %route_id, count = filtered.len(), "Upserted extra stops");
-                        if !filtered.is_empty() {
-                            db.upsert_stops(&filtered).await?;
-                        }
-                    }
-                    Err(e) => {
-                        tracing::error!(route = %route_id, error = %e, "Failed to fetch extra route stops");
-                    }
-                }
-            }
-            tokio::time::sleep(Duration::from_secs(13)).await;
+#[tokio::main]
+async fn main() -> Result<()> {
+    match dotenvy::dotenv() {
+        Ok(path) => eprintln!(".env loaded from {:?}", path),
+        Err(e) => eprintln!(".env not found: {}", e),
+    }
+
+    // Parsed after .env is loaded so its values count as env fallbacks
+    let cli = Cli::parse();
+    init_tracing(cli.log_format);
+    let explicit_config = cli.config.as_path() != Path::new(DEFAULT_CONFIG_PATH);
+    let file = FileConfig::load(&cli.config, explicit_config)?;
+    file.route_normalization.validate().context("Invalid [route_normalization] config")?;
+
+    let poll_interval_secs = cli
+        .interval_secs
+        .or(file.interval_secs)
+        .unwrap_or(DEFAULT_POLL_INTERVAL_SECS);
+    if poll_interval_secs < MIN_POLL_INTERVAL_SECS {
+        anyhow::bail!("interval_secs must be at least {}, got {}", MIN_POLL_INTERVAL_SECS, poll_interval_secs);
+    }
+    let database_url = cli
+        .database_url
+        .or(file.database_url)
+        .context("DATABASE_URL must be set (flag, .env or config file)")?;
+    let file_routes = file.routes.unwrap_or_default();
+
+    tracing::info!("Big Blue Bus Tracker starting");
+
+    let route_config = RouteConfig::from_env(cli.routes.as_deref(), &file_routes)?;
+
+    let addr = format!("0.0.0.0:{}", cli.port.or(file.port).unwrap_or(8080));
+
+    let mut builder = TransitClient::builder_from_env();
+    if std::env::var("TRANSIT_API_BASE").is_err() {
+        if let Some(base) = &file.api_base {
+            builder = builder.base_url(base);
         }
     }
+    if let Some(secs) = file.timeout_secs {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+    if let Some(retry) = &file.retry {
+        builder = builder.retry_policy(RetryPolicy {
+            max_attempts: retry.max_attempts,
+            base_delay: Duration::from_millis(retry.base_delay_ms),
+        });
+    }
+    for (name, value) in &file.headers {
+        builder = builder.header(name, value);
+    }
+    for (key, value) in &file.query {
+        builder = builder.query_param(key, value);
+    }
+    let archive = std::env::var("ARCHIVE_DIR").ok().map(FeedArchive::new);
+    if let Some(archive) = &archive {
+        tracing::info!(dir = %archive.dir().display(), "Archiving raw stop_departures responses");
+        builder = builder.archive(archive.clone());
+    }
+    let client = builder.build().context("Invalid Transit API client config")?;
+    let archive_retention_days: i64 = match std::env::var("ARCHIVE_RETENTION_DAYS") {
+        Ok(v) => v.parse().context("ARCHIVE_RETENTION_DAYS must be a number of days")?,
+        Err(_) => DEFAULT_ARCHIVE_RETENTION_DAYS,
+    };
+    let client = Arc::new(client);
+    let db = Arc::new(Database::new(&database_url).await?);
+    let history_size = match std::env::var("POLL_HISTORY_SIZE") {
+        Ok(v) => v.parse().context("POLL_HISTORY_SIZE must be a positive integer")?,
+        Err(_) => DEFAULT_HISTORY_SIZE,
+    };
+    let cache: api_server::Cache = Arc::new(RwLock::new(PollHistory::new(history_size)));
+    let late_threshold_secs: i32 = match std::env::var("LATE_THRESHOLD_SECS") {
+        Ok(v) => v.parse().context("LATE_THRESHOLD_SECS must be a number of seconds")?,
+        Err(_) => DEFAULT_LATE_THRESHOLD_SECS,
+    };
+    let retention_days: Option<i64> = match std::env::var("RETENTION_DAYS") {
+        Ok(v) => Some(v.parse().context("RETENTION_DAYS must be a number of days")?),
+        Err(_) => None,
+    };
+    let notifiers: Vec<WebhookNotifier> = match std::env::var("WEBHOOK_URLS") {
+        Ok(urls) => urls
+            .split(',')
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .map(WebhookNotifier::new)
+            .collect::<Result<_>>()?,
+        Err(_) => Vec::new(),
+    };
+    let notifiers = Arc::new(notifiers);
+    let failure_threshold = match std::env::var("BREAKER_FAILURE_THRESHOLD") {
+        Ok(v) => v.parse().context("BREAKER_FAILURE_THRESHOLD must be a positive integer")?,
+        Err(_) => DEFAULT_FAILURE_THRESHOLD,
+    };
+    let open_interval = match std::env::var("BREAKER_OPEN_SECS") {
+        Ok(v) => Duration::from_secs(v.parse().context("BREAKER_OPEN_SECS must be a number of seconds")?),
+        Err(_) => DEFAULT_OPEN_INTERVAL,
+    };
+    let buffer_rows = match std::env::var("WRITE_BUFFER_ROWS") {
+        Ok(v) => v.parse().context("WRITE_BUFFER_ROWS must be a positive integer")?,
+        Err(_) => DEFAULT_WRITE_BUFFER_ROWS,
+    };
+    let buffer_age = match std::env::var("WRITE_BUFFER_SECS") {
+        Ok(v) => Duration::from_secs(v.parse().context("WRITE_BUFFER_SECS must be a number of seconds")?),
+        Err(_) => DEFAULT_WRITE_BUFFER_AGE,
+    };
 
-    // Poll list is simply everything in the stops table — both full-route stops and extra stops
-    let stop_ids = db.get_all_stop_ids().await?;
+    // ── Full route bootstrap (once per route, the first time it's configured) ─
+    let bootstrapped = db.bootstrapped_routes().await?;
+    let new_routes: Vec<String> = route_config
+        .route_ids
+        .iter()
+        .filter(|r| !bootstrapped.contains(*r))
+        .cloned()
+        .collect();
+    if !new_routes.is_empty() {
+        bootstrap_routes(&client, &db, &new_routes).await?;
+    }
+    bootstrap_extra_stops(&client, &db, &route_config).await?;
 
-    if stop_ids.is_empty() {
+    // Poll list: the configured routes' stops plus the extra watch-point stops
+    let (targets, stops) = poll_targets(&db, &route_config).await?;
+
+    if targets.stop_ids.is_empty() {
         anyhow::bail!("No stops configured — check ROUTE_IDS and EXTRA_ROUTE_IDS");
     }
 
-    // Load full stop metadata (with coordinates) for the map
-    let stops = Arc::new(db.get_all_stops().await?);
+    // Full stop metadata (with coordinates) for the map
+    let stops = Arc::new(RwLock::new(stops));
 
-    // Seed cache from last poll so the map is immediately usable after restart
-    if let Ok(Some((polled_at, departures))) = db.load_latest_poll().await {
+    // Seed cache from last poll so the map is immediately usable after restart.
+    // Prefer the binary snapshot cache (cheap) and fall back to the DB.
+    // Either way the seed is marked stale until the first fresh poll.
+    let snapshot_path = std::env::var("SNAPSHOT_CACHE_PATH").ok().map(|p| Arc::new(PathBuf::from(p)));
+    let cached = match &snapshot_path {
+        Some(path) => snapshot::load(path).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "Failed to load snapshot cache");
+            None
+        }),
+        None => None,
+    };
+    if let Some(poll) = cached {
+        tracing::info!(departures = poll.departures.len(), "Seeded cache from snapshot file");
+        cache.write().await.push_stale(poll);
+    } else if let Ok(Some((polled_at, departures))) = db.load_latest_poll().await {
         tracing::info!(departures = departures.len(), "Seeded cache from last poll");
-        *cache.write().await = Some(bigbluebunch::models::PollResult { polled_at, departures });
+        cache.write().await.push_stale(bigbluebunch::models::PollResult { polled_at, departures });
     }
 
     tracing::info!(
-        stops = stop_ids.len(),
-        chunks = stop_ids.chunks(100).count(),
-        interval_secs = POLL_INTERVAL_SECS,
+        stops = targets.stop_ids.len(),
+        chunks = targets.stop_ids.chunks(100).count(),
+        interval_secs = poll_interval_secs,
         "Ready — polling active weekdays 8–11am and 3–6pm"
     );
 
-    let chunks_per_poll = stop_ids.chunks(100).count();
+    let targets = Arc::new(RwLock::new(targets));
+    let polls_done = Arc::new(AtomicUsize::new(0));
+    // Rows actually inserted, not fetched: duplicates are skipped
+    let departures_stored = Arc::new(AtomicUsize::new(0));
 
     // ── Departures poll task ─────────────────────────────────────────────────
-    {
+    // Stopped through `stop_polling` on shutdown, so it can flush the buffer
+    let (stop_polling, mut stopping) = watch::channel(false);
+    let poll_task = {
         let client_poll = Arc::clone(&client);
         let db_poll = Arc::clone(&db);
         let cache_poll = Arc::clone(&cache);
-        let stop_ids_poll = stop_ids.clone();
+        let targets_poll = Arc::clone(&targets);
+        let snapshot_poll = snapshot_path.clone();
+        let mut breaker = CircuitBreaker::new(failure_threshold, open_interval);
+        let polls_done = Arc::clone(&polls_done);
+        let departures_stored = Arc::clone(&departures_stored);
+        let notifiers = Arc::clone(&notifiers);
+        let mut events = EventTracker::new();
+        let mut stale_events = EventTracker::new();
+        let mut last_success: Option<FeedTime> = None;
+        let mut failing_since: Option<FeedTime> = None;
 
         tokio::spawn(async move {
+            let mut writer = BufferedWriter::new(&db_poll, buffer_rows, buffer_age);
             loop {
-                // Sleep until the next 15-minute boundary (:00, :15, :30, :45)
+                // Sleep until the next interval boundary (:00, :15, :30, :45 by default)
                 let now = Utc::now();
-                let secs_into_interval = (now.timestamp() % POLL_INTERVAL_SECS as i64) as u64;
-                let secs_until_next = POLL_INTERVAL_SECS - secs_into_interval;
-                tokio::time::sleep(Duration::from_secs(secs_until_next)).await;
+                let secs_into_interval = (now.timestamp() % poll_interval_secs as i64) as u64;
+                let secs_until_next = poll_interval_secs - secs_into_interval;
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(secs_until_next)) => {}
+                    _ = stopping.changed() => break,
+                }
 
                 if !is_active_window() {
+                    // Don't hold the window's last polls until the next one opens
+                    match writer.flush().await {
+                        Ok(stored) => {
+                            departures_stored.fetch_add(stored, Ordering::Relaxed);
+                        }
+                        Err(e) => {
+                            departures_stored.fetch_add(e.inserted, Ordering::Relaxed);
+                            tracing::error!(error = %e, buffered = writer.len(), "Failed to persist departures");
+                        }
+                    }
+                    continue;
+                }
+                if !breaker.allow(Instant::now()) {
                     continue;
                 }
-                match tokio::time::timeout(
-                    Duration::from_secs(POLL_INTERVAL_SECS),
-                    poll_once(&client_poll, &db_poll, &stop_ids_poll, true),
+                let targets = targets_poll.read().await.clone();
+                // Nothing is written until the poll completes, so it's safe to abandon
+                let poll = tokio::select! {
+                    poll = tokio::time::timeout(
+                        Duration::from_secs(poll_interval_secs),
+                        fetch_poll(client_poll.as_ref(), &targets, true),
+                    ) => poll,
+                    _ = stopping.changed() => break,
+                };
+                match poll {
+                    Ok(Some(result)) => {
+                        match writer.push(result.polled_at, &result.departures).await {
+                            Ok(stored) => {
+                                departures_stored.fetch_add(stored, Ordering::Relaxed);
+                            }
+                            Err(e) => {
+                                departures_stored.fetch_add(e.inserted, Ordering::Relaxed);
+                                tracing::error!(error = %e, buffered = writer.len(), "Failed to persist departures");
+                            }
+                        }
+                        breaker.record_success();
+                        last_success = Some(result.polled_at);
+                        failing_since = None;
+                        stale_events.transitions(Vec::new());
+                        let detected = detect_events(
+                            &result.departures,
+                            late_threshold_secs,
+                            DEFAULT_BUNCHING_THRESHOLD_SECS,
+                        );
+                        dispatch(&notifiers, events.transitions(detected));
+                        polls_done.fetch_add(1, Ordering::Relaxed);
+                        if let Some(path) = &snapshot_poll {
+                            if let Err(e) = snapshot::save(path, &result) {
+                                tracing::warn!(error = %e, "Failed to write snapshot cache");
+                            }
+                        }
+                        let late = trips_exceeding_delay(&result.departures, late_threshold_secs);
+                        if !late.is_empty() {
+                            tracing::warn!(count = late.len(), threshold_secs = late_threshold_secs, "Trips running late");
+                            let summaries = delay_summary_by_trip(&result.departures);
+                            for trip in &late {
+                                tracing::warn!(
+                                    route = %trip.route_short_name,
+                                    headsign = trip.headsign.as_deref().unwrap_or("?"),
+                                    trip = %trip.rt_trip_id,
+                                    delay_min = trip.max_delay_seconds / 60,
+                                    summary = %summaries[&trip.rt_trip_id],
+                                    "Late trip"
+                                );
+                            }
+                        }
+                        let mut cache = cache_poll.write().await;
+                        // Only diff against a fresh poll, not a seed restored at startup
+                        let has_previous = !cache.is_empty() && !cache.is_stale();
+                        cache.push(result);
+                        if has_previous {
+                            if let (Some(latest), Some(previous)) = (cache.latest(), cache.previous()) {
+                                let diff = latest.diff(previous);
+                                tracing::info!(
+                                    added = diff.added,
+                                    removed = diff.removed,
+                                    delays_worsened = diff.delays_worsened,
+                                    delays_improved = diff.delays_improved,
+                                    "Since last poll"
+                                );
+                            }
+                        }
+                    }
+                    Ok(None) => {
+                        tracing::warn!("Poll returned no result");
+                        breaker.record_failure(Instant::now());
+                        let since = *failing_since.get_or_insert_with(FeedTime::now);
+                        notify_if_stale(&notifiers, &mut stale_events, last_success, since, poll_interval_secs);
+                    }
+                    Err(_) => {
+                        tracing::error!(timeout_secs = poll_interval_secs, "Poll timed out");
+                        breaker.record_failure(Instant::now());
+                        let since = *failing_since.get_or_insert_with(FeedTime::now);
+                        notify_if_stale(&notifiers, &mut stale_events, last_success, since, poll_interval_secs);
+                    }
+                }
+            }
+
+            match writer.flush().await {
+                Ok(stored) => {
+                    departures_stored.fetch_add(stored, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    departures_stored.fetch_add(e.inserted, Ordering::Relaxed);
+                    tracing::error!(error = %e, lost = writer.len(), "Failed to flush buffered departures");
+                }
+            }
+        })
+    };
+
+    // Tasks that run until shutdown, stopped before the pool closes
+    let mut background = Vec::new();
+
+    // ── Daily prune of old departure_log rows (only with RETENTION_DAYS) ─────
+    if let Some(days) = retention_days {
+        let db_prune = Arc::clone(&db);
+        background.push(tokio::spawn(async move {
+            let mut daily = tokio::time::interval(Duration::from_secs(86_400));
+            loop {
+                daily.tick().await;
+                if let Err(e) = db_prune.prune_keeping_days(days).await {
+                    tracing::error!(error = %e, "Failed to prune departure_log");
+                }
+            }
+        }));
+    }
+
+    // ── Daily prune of archived responses (only with ARCHIVE_DIR) ────────────
+    if let Some(archive) = archive {
+        background.push(tokio::spawn(async move {
+            let mut daily = tokio::time::interval(Duration::from_secs(86_400));
+            loop {
+                daily.tick().await;
+                if let Err(e) = archive.prune_keeping_days(archive_retention_days).await {
+                    tracing::error!(error = %e, "Failed to prune response archive");
+                }
+            }
+        }));
+    }
+
+    // ── SIGHUP: reload route config from .env and the config file ───────────
+    {
+        let client_hup = Arc::clone(&client);
+        let db_hup = Arc::clone(&db);
+        let targets_hup = Arc::clone(&targets);
+        let stops_hup = Arc::clone(&stops);
+        let mut current = route_config;
+        let cli_routes = cli.routes.clone();
+        let config_path = cli.config.clone();
+
+        background.push(tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(s) => s,
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to listen for SIGHUP");
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                let reloaded = reload_routes(
+                    &client_hup,
+                    &db_hup,
+                    &current,
+                    cli_routes.as_deref(),
+                    &config_path,
+                    explicit_config,
                 )
-                .await
-                {
-                    Ok(Some(result)) => *cache_poll.write().await = Some(result),
-                    Ok(None) => tracing::warn!("Poll returned no result"),
-                    Err(_) => tracing::error!("Poll timed out after {}s", POLL_INTERVAL_SECS),
+                .await;
+                match reloaded {
+                    Ok((next, targets, stops)) => {
+                        tracing::info!(stops = targets.stop_ids.len(), "Poll list refreshed");
+                        *targets_hup.write().await = targets;
+                        *stops_hup.write().await = stops;
+                        current = next;
+                    }
+                    Err(e) => {
+                        tracing::error!(error = %e, "Route reload failed — keeping previous config");
+                    }
                 }
             }
-        });
+        }));
     }
 
-    api_server::run_server(&addr, cache, stops, client, db, Arc::new(stop_ids), chunks_per_poll).await?;
+    let terminate = signal(SignalKind::terminate()).context("Failed to listen for SIGTERM")?;
+    let shutdown = shutdown_signal(terminate);
+
+    let state = api_server::AppState {
+        cache,
+        stops,
+        client,
+        db: Arc::clone(&db),
+        targets,
+        snapshot_path,
+        poll_interval: Duration::from_secs(poll_interval_secs),
+        route_normalization: Arc::new(file.route_normalization),
+    };
+    api_server::run_server(&addr, state, shutdown).await?;
+
+    let _ = stop_polling.send(true);
+    if let Err(e) = poll_task.await {
+        tracing::error!(error = %e, "Poll task failed");
+    }
+    // A prune or reload cut off here is simply redone on the next start
+    for task in background {
+        task.abort();
+        let _ = task.await;
+    }
+
+    // Waits for checked-out connections, so an in-flight departure_log
+    // transaction commits before we exit.
+    db.close().await;
+    tracing::info!(
+        polls = polls_done.load(Ordering::Relaxed),
+        departures_stored = departures_stored.load(Ordering::Relaxed),
+        "Shut down cleanly"
+    );
     Ok(())
 }
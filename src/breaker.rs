@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+pub const DEFAULT_OPEN_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Stops the poll loop from hammering a dead Transit API.
+///
+/// After `threshold` consecutive failed polls the breaker opens and only lets
+/// a probe through once per `open_interval`. The first successful poll closes
+/// it again.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    open_interval: Duration,
+    consecutive_failures: u32,
+    /// Set while open: when the last probe (or the opening failure) happened
+    last_attempt: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, open_interval: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            open_interval,
+            consecutive_failures: 0,
+            last_attempt: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.last_attempt.is_some()
+    }
+
+    /// Whether a poll should go out now. While open, allows one probe per
+    /// `open_interval`.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.last_attempt {
+            None => true,
+            Some(last) if now.duration_since(last) >= self.open_interval => {
+                tracing::info!("Circuit breaker half-open, probing Transit API");
+                self.last_attempt = Some(now);
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        if self.is_open() {
+            tracing::info!(
+                failures = self.consecutive_failures,
+                "Circuit breaker closed, polling resumed"
+            );
+        }
+        self.consecutive_failures = 0;
+        self.last_attempt = None;
+    }
+
+    pub fn record_failure(&mut self, now: Instant) {
+        self.consecutive_failures += 1;
+        if self.is_open() {
+            tracing::warn!(
+                failures = self.consecutive_failures,
+                "Probe failed, circuit breaker stays open"
+            );
+            self.last_attempt = Some(now);
+        } else if self.consecutive_failures >= self.threshold {
+            tracing::error!(
+                failures = self.consecutive_failures,
+                retry_in_secs = self.open_interval.as_secs(),
+                "Circuit breaker open, backing off polls"
+            );
+            self.last_attempt = Some(now);
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_OPEN_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPEN: Duration = Duration::from_secs(3600);
+
+    /// A breaker opened by three failures at `t0`.
+    fn opened(t0: Instant) -> CircuitBreaker {
+        let mut breaker = CircuitBreaker::new(3, OPEN);
+        for _ in 0..3 {
+            assert!(breaker.allow(t0));
+            breaker.record_failure(t0);
+        }
+        assert!(breaker.is_open());
+        breaker
+    }
+
+    #[test]
+    fn closed_open_half_open_closed() {
+        let t0 = Instant::now();
+        let mut breaker = CircuitBreaker::new(3, OPEN);
+
+        // Below the threshold it stays closed
+        breaker.record_failure(t0);
+        breaker.record_failure(t0);
+        assert!(!breaker.is_open());
+        assert!(breaker.allow(t0));
+
+        // A success resets the count, so three more are needed to open
+        breaker.record_success();
+        breaker.record_failure(t0);
+        breaker.record_failure(t0);
+        assert!(!breaker.is_open());
+        breaker.record_failure(t0);
+        assert!(breaker.is_open());
+
+        // Open: nothing goes out until the interval has passed
+        assert!(!breaker.allow(t0));
+        assert!(!breaker.allow(t0 + OPEN - Duration::from_secs(1)));
+
+        // Half-open: one probe, then closed again once it succeeds
+        assert!(breaker.allow(t0 + OPEN));
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        assert!(breaker.allow(t0 + OPEN));
+    }
+
+    #[test]
+    fn failed_probe_waits_another_interval() {
+        let t0 = Instant::now();
+        let mut breaker = opened(t0);
+
+        let probe = t0 + OPEN;
+        assert!(breaker.allow(probe));
+        // Only one probe per interval
+        assert!(!breaker.allow(probe));
+        breaker.record_failure(probe);
+        assert!(breaker.is_open());
+
+        // Measured from the failed probe, not from when it first opened
+        assert!(!breaker.allow(probe + OPEN - Duration::from_secs(1)));
+        assert!(breaker.allow(probe + OPEN));
+        breaker.record_success();
+        assert!(!breaker.is_open());
+    }
+}
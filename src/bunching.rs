@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::models::{Departure, FeedTime, LoggedDeparture};
+
+pub const DEFAULT_BUNCHING_THRESHOLD_SECS: i64 = 120;
+pub const DEFAULT_SHORT_HEADWAY_FRACTION: f64 = 0.25;
+
+/// Two buses on the same route and direction predicted to leave a stop less
+/// than the threshold apart.
+#[derive(Debug, Clone, Serialize)]
+pub struct BunchingEvent {
+    pub global_stop_id: String,
+    pub route_short_name: String,
+    pub headsign: Option<String>,
+    pub leading_trip_id: Option<String>,
+    pub trailing_trip_id: Option<String>,
+    pub leading_departure: FeedTime,
+    pub trailing_departure: FeedTime,
+    pub gap_secs: i64,
+}
+
+/// Find consecutive departures at the same stop, route and headsign whose
+/// departure times are less than `threshold_secs` apart.
+///
+/// The Transit API gives no vehicle positions, so bunching is measured in
+/// time at each stop rather than distance. Cancelled departures are ignored,
+/// as are pairs where neither side is real-time (the schedule itself isn't
+/// bunching) and a trip paired with itself.
+pub fn detect_bunching(departures: &[Departure], threshold_secs: i64) -> Vec<BunchingEvent> {
+    let mut groups: HashMap<(&str, &str, Option<&str>), Vec<&Departure>> = HashMap::new();
+    for d in departures.iter().filter(|d| !d.is_cancelled) {
+        groups
+            .entry((&d.global_stop_id, &d.route_short_name, d.headsign.as_deref()))
+            .or_default()
+            .push(d);
+    }
+
+    let mut events = Vec::new();
+    for ((stop, route, headsign), mut group) in groups {
+        group.sort_by_key(|d| d.departure_time);
+        for pair in group.windows(2) {
+            let (lead, trail) = (pair[0], pair[1]);
+            if !(lead.is_real_time || trail.is_real_time) {
+                continue;
+            }
+            if lead.rt_trip_id.is_some() && lead.rt_trip_id == trail.rt_trip_id {
+                continue;
+            }
+            let gap_secs = trail.departure_time.secs() - lead.departure_time.secs();
+            if gap_secs < threshold_secs {
+                events.push(BunchingEvent {
+                    global_stop_id: stop.to_string(),
+                    route_short_name: route.to_string(),
+                    headsign: headsign.map(str::to_string),
+                    leading_trip_id: lead.rt_trip_id.clone(),
+                    trailing_trip_id: trail.rt_trip_id.clone(),
+                    leading_departure: lead.departure_time,
+                    trailing_departure: trail.departure_time,
+                    gap_secs,
+                });
+            }
+        }
+    }
+
+    events.sort_by(|a, b| {
+        (&a.global_stop_id, a.leading_departure).cmp(&(&b.global_stop_id, b.leading_departure))
+    });
+    events
+}
+
+/// `detect_bunching` over each poll in a stretch of departure_log, with a pair
+/// of trips bunched at a stop counted once however many polls saw it. The
+/// first poll that saw each pair wins.
+pub fn bunching_across_polls(rows: &[LoggedDeparture], threshold_secs: i64) -> Vec<BunchingEvent> {
+    let mut polls: BTreeMap<FeedTime, Vec<Departure>> = BTreeMap::new();
+    for row in rows {
+        polls.entry(row.polled_at).or_default().push(row.departure.clone());
+    }
+
+    let mut seen = HashSet::new();
+    let mut events = Vec::new();
+    for departures in polls.values() {
+        for event in detect_bunching(departures, threshold_secs) {
+            let key = (
+                event.global_stop_id.clone(),
+                event.leading_trip_id.clone(),
+                event.trailing_trip_id.clone(),
+            );
+            // Trips without IDs can't be matched across polls; keep every sighting
+            if event.leading_trip_id.is_none() || seen.insert(key) {
+                events.push(event);
+            }
+        }
+    }
+    events
+}
+
+/// Gaps between consecutive predicted departures at `stop_id`, in time order.
+///
+/// Pass one route's departures (and one headsign, if the route runs both
+/// ways through the stop). Cancelled departures are skipped and a trip
+/// listed twice only counts once, at its earliest time. Fewer than two
+/// departures gives no gaps.
+pub fn headways_at_stop(departures: &[Departure], stop_id: &str) -> Vec<Duration> {
+    let mut times: Vec<(FeedTime, Option<&str>)> = departures
+        .iter()
+        .filter(|d| d.global_stop_id == stop_id && !d.is_cancelled)
+        .map(|d| (d.departure_time, d.rt_trip_id.as_deref()))
+        .collect();
+    times.sort();
+    // Another trip can fall between a trip's listings, so look past neighbours
+    let mut seen = HashSet::new();
+    times.retain(|(_, trip)| trip.is_none_or(|t| seen.insert(t)));
+
+    times
+        .windows(2)
+        .map(|pair| Duration::from_secs((pair[1].0.secs() - pair[0].0.secs()).max(0) as u64))
+        .collect()
+}
+
+/// Indices into `headways` shorter than `fraction` of their median, i.e.
+/// likely bunching relative to how often the route normally runs. `fraction`
+/// must be within 0.0..=1.0.
+pub fn short_headways(headways: &[Duration], fraction: f64) -> Result<Vec<usize>> {
+    if !(0.0..=1.0).contains(&fraction) {
+        anyhow::bail!("Headway fraction {} is outside 0.0..=1.0", fraction);
+    }
+    if headways.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut sorted = headways.to_vec();
+    sorted.sort();
+    let mid = sorted.len() / 2;
+    let median = if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2
+    } else {
+        sorted[mid]
+    };
+    let cutoff = median.mul_f64(fraction);
+
+    Ok(headways
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| **h < cutoff)
+        .map(|(i, _)| i)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(headways: &[Duration]) -> Vec<u64> {
+        headways.iter().map(Duration::as_secs).collect()
+    }
+
+    #[test]
+    fn headways_count_each_trip_once() {
+        let cancelled = Departure::sample().trip("1004").departs(1_500).cancelled();
+        let departures = [
+            Departure::sample().trip("1002").departs(1_600),
+            Departure::sample().trip("1001").departs(1_000),
+            // Trip 1001 listed again, with 1002 in between once sorted
+            Departure::sample().trip("1001").departs(1_700),
+            Departure::sample().trip("1003").departs(2_200),
+            cancelled,
+            Departure::sample().stop("BBB:7024").trip("1005").departs(1_100),
+        ];
+        assert_eq!(secs(&headways_at_stop(&departures, "BBB:7023")), [600, 600]);
+        assert!(headways_at_stop(&departures, "BBB:7024").is_empty());
+        assert!(headways_at_stop(&departures, "BBB:9999").is_empty());
+    }
+
+    #[test]
+    fn headways_keep_departures_without_trip_ids() {
+        let departures = [
+            Departure::sample().departs(1_000),
+            Departure::sample().departs(1_300),
+            Departure::sample().departs(1_900),
+        ];
+        assert_eq!(secs(&headways_at_stop(&departures, "BBB:7023")), [300, 600]);
+    }
+
+    #[test]
+    fn short_headways_against_the_median() {
+        let headways: Vec<Duration> = [600, 60, 620, 580, 200].map(Duration::from_secs).to_vec();
+        // Median 580 s: below 145 s at 0.25, below 290 s at 0.5
+        assert_eq!(short_headways(&headways, 0.25).unwrap(), [1]);
+        assert_eq!(short_headways(&headways, 0.5).unwrap(), [1, 4]);
+        assert!(short_headways(&headways, 0.0).unwrap().is_empty());
+
+        // Even count: median of 300 and 600 is 450
+        let even: Vec<Duration> = [100, 300, 600, 900].map(Duration::from_secs).to_vec();
+        assert_eq!(short_headways(&even, 0.25).unwrap(), [0]);
+        assert!(short_headways(&[], 0.25).unwrap().is_empty());
+    }
+
+    #[test]
+    fn short_headways_rejects_bad_fractions() {
+        let headways = [Duration::from_secs(600)];
+        for fraction in [-0.1, 1.5, f64::NAN, f64::INFINITY] {
+            assert!(short_headways(&headways, fraction).is_err(), "fraction {}", fraction);
+        }
+    }
+
+    #[test]
+    fn bunching_threshold_is_exclusive() {
+        let threshold = DEFAULT_BUNCHING_THRESHOLD_SECS;
+        let just_under = [
+            Departure::sample().trip("1001").departs(1_000),
+            Departure::sample().trip("1002").departs(1_000 + threshold - 1),
+        ];
+        let events = detect_bunching(&just_under, threshold);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].gap_secs, threshold - 1);
+        assert_eq!(events[0].leading_trip_id.as_deref(), Some("1001"));
+        assert_eq!(events[0].trailing_trip_id.as_deref(), Some("1002"));
+
+        let at_threshold = [
+            Departure::sample().trip("1001").departs(1_000),
+            Departure::sample().trip("1002").departs(1_000 + threshold),
+        ];
+        assert!(detect_bunching(&at_threshold, threshold).is_empty());
+        let just_over = [
+            Departure::sample().trip("1001").departs(1_000),
+            Departure::sample().trip("1002").departs(1_000 + threshold + 1),
+        ];
+        assert!(detect_bunching(&just_over, threshold).is_empty());
+    }
+
+    #[test]
+    fn single_departure_is_never_bunched() {
+        let single = [Departure::sample().trip("1001").departs(1_000)];
+        assert!(detect_bunching(&single, DEFAULT_BUNCHING_THRESHOLD_SECS).is_empty());
+        assert!(detect_bunching(&[], DEFAULT_BUNCHING_THRESHOLD_SECS).is_empty());
+
+        // Close together, but at different stops
+        let apart = [
+            Departure::sample().trip("1001").departs(1_000),
+            Departure::sample().stop("BBB:7024").trip("1002").departs(1_030),
+        ];
+        assert!(detect_bunching(&apart, DEFAULT_BUNCHING_THRESHOLD_SECS).is_empty());
+    }
+}
@@ -1,7 +1,40 @@
 use anyhow::{Context, Result};
-use sqlx::{postgres::PgPoolOptions, Row, PgPool};
+use futures::TryStreamExt;
+use std::collections::{HashMap, HashSet};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{migrate::Migrator, PgPool, Postgres, QueryBuilder, Row};
+use std::time::{Duration, Instant};
 
-use crate::models::{Departure, Stop};
+use crate::analysis::OnTimePerformance;
+use crate::models::{AccuracyStats, AccuracyWindow, Departure, FeedTime, LoggedDeparture, Stop};
+
+/// Columns mapped by `Departure`'s `FromRow`.
+const DEPARTURE_COLUMNS: &str = "global_stop_id, global_route_id, route_short_name, headsign,
+    departure_time, scheduled_departure_time, delay_seconds,
+    is_real_time, is_cancelled, rt_trip_id";
+
+/// Rows per multi-row INSERT. Each row binds 11 parameters.
+pub const DEFAULT_INSERT_CHUNK_SIZE: usize = 1000;
+/// Postgres caps a statement at 65535 bind parameters.
+const MAX_INSERT_CHUNK_SIZE: usize = 65535 / 11;
+
+/// `BufferedWriter` rows that force a write regardless of age.
+pub const DEFAULT_WRITE_BUFFER_ROWS: usize = 10_000;
+/// `BufferedWriter` age that forces a write. Zero writes every poll as it
+/// arrives.
+pub const DEFAULT_WRITE_BUFFER_AGE: Duration = Duration::ZERO;
+/// `BufferedWriter` rows kept while writes keep failing; past this the
+/// oldest are dropped.
+pub const DEFAULT_WRITE_BUFFER_CAP: usize = 100_000;
+
+const UPSERT_STOP: &str = "INSERT INTO stops (global_stop_id, stop_name, lat, lon)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT(global_stop_id) DO UPDATE SET
+      stop_name = EXCLUDED.stop_name,
+      lat = EXCLUDED.lat,
+      lon = EXCLUDED.lon";
+
+static MIGRATOR: Migrator = sqlx::migrate!();
 
 pub struct Database {
     pool: PgPool,
@@ -9,9 +42,16 @@ pub struct Database {
 
 impl Database {
     pub async fn new(database_url: &str) -> Result<Self> {
+        let options = database_url.parse().context("Invalid database URL")?;
+        Self::connect_with(options).await
+    }
+
+    /// `new` from already-parsed options, e.g. to pick another database on
+    /// the same server.
+    pub async fn connect_with(options: PgConnectOptions) -> Result<Self> {
         let pool = PgPoolOptions::new()
             .max_connections(5)
-            .connect(database_url)
+            .connect_with(options)
             .await
             .context("Failed to connect to database")?;
 
@@ -20,39 +60,41 @@ impl Database {
         Ok(db)
     }
 
+    /// Close the pool, waiting for checked-out connections to finish.
+    ///
+    /// `Drop` can't be async, so an in-flight insert would otherwise be cut off
+    /// when the runtime shuts down. Call this on the shutdown path before exit.
+    pub async fn close(&self) {
+        self.pool.close().await;
+        tracing::debug!("Database pool closed");
+    }
+
+    /// Apply any migrations in `migrations/` the database hasn't seen yet.
+    ///
+    /// Databases created before migrations were tracked already have the
+    /// tables; the initial migration uses IF NOT EXISTS so it records itself
+    /// as applied without touching them.
     async fn init_schema(&self) -> Result<()> {
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS stops (
-                global_stop_id TEXT PRIMARY KEY,
-                stop_name TEXT NOT NULL,
-                lat DOUBLE PRECISION NOT NULL,
-                lon DOUBLE PRECISION NOT NULL
-            )",
-        )
-        .execute(&self.pool)
-        .await
-        .context("Failed to create stops table")?;
+        // Fails on a fresh database where sqlx hasn't created its table yet
+        let applied: Vec<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+                .fetch_all(&self.pool)
+                .await
+                .unwrap_or_default();
 
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS departure_log (
-                id BIGSERIAL PRIMARY KEY,
-                polled_at BIGINT NOT NULL,
-                global_stop_id TEXT NOT NULL,
-                global_route_id TEXT NOT NULL,
-                route_short_name TEXT NOT NULL,
-                headsign TEXT,
-                departure_time BIGINT NOT NULL,
-                scheduled_departure_time BIGINT NOT NULL,
-                delay_seconds INTEGER,
-                is_real_time BOOLEAN NOT NULL DEFAULT FALSE,
-                is_cancelled BOOLEAN NOT NULL DEFAULT FALSE,
-                rt_trip_id TEXT
-            )",
-        )
-        .execute(&self.pool)
-        .await
-        .context("Failed to create departure_log table")?;
+        MIGRATOR.run(&self.pool).await.context("Failed to run migrations")?;
 
+        for m in MIGRATOR.iter().filter(|m| !applied.contains(&m.version)) {
+            tracing::info!(version = m.version, description = %m.description, "Applied migration");
+        }
+        tracing::debug!("Database schema up to date");
+        Ok(())
+    }
+
+    /// Recreate the departure_log indexes after `drop_indexes`. Idempotent.
+    /// The unique index isn't among them: it's created (after removing any
+    /// duplicates) by the migrations, and never dropped.
+    pub async fn create_indexes(&self) -> Result<()> {
         sqlx::query(
             "CREATE INDEX IF NOT EXISTS idx_log_polled_at ON departure_log(polled_at)",
         )
@@ -67,43 +109,123 @@ impl Database {
         .await
         .context("Failed to create stop index")?;
 
-        tracing::debug!("Database schema initialized");
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_log_trip ON departure_log(rt_trip_id, polled_at)",
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create trip index")?;
+
         Ok(())
     }
 
-    pub async fn stops_initialized(&self) -> Result<bool> {
-        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM stops")
-            .fetch_one(&self.pool)
+    /// Drop the departure_log indexes ahead of a bulk import, so each row
+    /// doesn't pay for index maintenance. Rebuilding once afterwards is much
+    /// faster (local Postgres 15, 100k rows: 792 ms indexed, against 271 ms
+    /// to load plus 183 ms to rebuild). Queries by stop or poll time degrade
+    /// to sequential scans until `create_indexes` runs again, so always pair
+    /// the two.
+    ///
+    /// The unique index stays, since it's what makes inserts skip duplicates.
+    pub async fn drop_indexes(&self) -> Result<()> {
+        sqlx::query("DROP INDEX IF EXISTS idx_log_polled_at")
+            .execute(&self.pool)
+            .await
+            .context("Failed to drop polled_at index")?;
+
+        sqlx::query("DROP INDEX IF EXISTS idx_log_stop")
+            .execute(&self.pool)
             .await
-            .context("Failed to count stops")?;
-        Ok(count > 0)
+            .context("Failed to drop stop index")?;
+
+        sqlx::query("DROP INDEX IF EXISTS idx_log_trip")
+            .execute(&self.pool)
+            .await
+            .context("Failed to drop trip index")?;
+
+        Ok(())
+    }
+
+    /// Round-trip a trivial query, to confirm the database is reachable.
+    pub async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .context("Database did not answer SELECT 1")?;
+        Ok(())
     }
 
     pub async fn upsert_stops(&self, stops: &[Stop]) -> Result<()> {
         let mut tx = self.pool.begin().await.context("Failed to start transaction")?;
 
         for stop in stops {
+            sqlx::query(UPSERT_STOP)
+                .bind(&stop.global_stop_id)
+                .bind(&stop.stop_name)
+                .bind(stop.lat)
+                .bind(stop.lon)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to upsert stop")?;
+        }
+
+        tx.commit().await.context("Failed to commit stops")?;
+        Ok(())
+    }
+
+    /// Upsert every stop on `route_id` and record that the route serves them,
+    /// in one transaction.
+    pub async fn upsert_route_stops(&self, route_id: &str, stops: &[Stop]) -> Result<()> {
+        let mut tx = self.pool.begin().await.context("Failed to start transaction")?;
+
+        for stop in stops {
+            sqlx::query(UPSERT_STOP)
+                .bind(&stop.global_stop_id)
+                .bind(&stop.stop_name)
+                .bind(stop.lat)
+                .bind(stop.lon)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to upsert stop")?;
+
             sqlx::query(
-                "INSERT INTO stops (global_stop_id, stop_name, lat, lon)
-                 VALUES ($1, $2, $3, $4)
-                 ON CONFLICT(global_stop_id) DO UPDATE SET
-                   stop_name = EXCLUDED.stop_name,
-                   lat = EXCLUDED.lat,
-                   lon = EXCLUDED.lon",
+                "INSERT INTO route_stops (global_route_id, global_stop_id)
+                 VALUES ($1, $2)
+                 ON CONFLICT DO NOTHING",
             )
+            .bind(route_id)
             .bind(&stop.global_stop_id)
-            .bind(&stop.stop_name)
-            .bind(stop.lat)
-            .bind(stop.lon)
             .execute(&mut *tx)
             .await
-            .context("Failed to upsert stop")?;
+            .context("Failed to record route stop")?;
         }
 
-        tx.commit().await.context("Failed to commit stops")?;
+        tx.commit().await.context("Failed to commit route stops")?;
         Ok(())
     }
 
+    /// Routes with stops recorded by `upsert_route_stops`.
+    pub async fn bootstrapped_routes(&self) -> Result<HashSet<String>> {
+        let routes: Vec<String> = sqlx::query_scalar("SELECT DISTINCT global_route_id FROM route_stops")
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to query bootstrapped routes")?;
+        Ok(routes.into_iter().collect())
+    }
+
+    /// Stops served by any of `route_ids`, sorted, each once.
+    pub async fn stop_ids_for_routes(&self, route_ids: &[String]) -> Result<Vec<String>> {
+        sqlx::query_scalar(
+            "SELECT DISTINCT global_stop_id FROM route_stops
+             WHERE global_route_id = ANY($1)
+             ORDER BY global_stop_id",
+        )
+        .bind(route_ids)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query stops for routes")
+    }
+
     pub async fn get_all_stop_ids(&self) -> Result<Vec<String>> {
         let rows = sqlx::query("SELECT global_stop_id FROM stops")
             .fetch_all(&self.pool)
@@ -145,42 +267,685 @@ impl Database {
         Ok((row.get("total"), row.get("today")))
     }
 
+    /// Logged departures for one route (global route ID or short name).
+    pub async fn count_departures_by_route(&self, route: &str) -> Result<i64> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM departure_log WHERE global_route_id = $1 OR route_short_name = $1",
+        )
+        .bind(route)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to count departures for route")
+    }
+
+    /// Logged departures per global route ID, in one query.
+    pub async fn counts_by_route(&self) -> Result<HashMap<String, i64>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT global_route_id, COUNT(*) FROM departure_log GROUP BY global_route_id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to count departures by route")?;
+        Ok(rows.into_iter().collect())
+    }
+
+    pub async fn load_latest_poll(&self) -> Result<Option<(FeedTime, Vec<Departure>)>> {
+        let row = sqlx::query("SELECT MAX(polled_at) AS latest FROM departure_log")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to query latest poll")?;
+        let polled_at: Option<FeedTime> = row.get("latest");
+        let Some(polled_at) = polled_at else { return Ok(None) };
+
+        let departures = sqlx::query_as::<_, Departure>(&format!(
+            "SELECT {} FROM departure_log WHERE polled_at = $1",
+            DEPARTURE_COLUMNS
+        ))
+        .bind(polled_at)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load latest poll")?;
+
+        Ok(Some((polled_at, departures)))
+    }
+
+    /// Highest departure_log id so far (0 when empty); the starting cursor for
+    /// `departures_after`.
+    pub async fn max_departure_id(&self) -> Result<i64> {
+        let id: Option<i64> = sqlx::query_scalar("SELECT MAX(id) FROM departure_log")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to query max departure id")?;
+        Ok(id.unwrap_or(0))
+    }
+
+    /// Rows inserted after `after_id`, oldest first (keyset pagination on id).
+    /// `route` matches either the global route ID or the short name.
+    pub async fn departures_after(
+        &self,
+        after_id: i64,
+        route: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<LoggedDeparture>> {
+        sqlx::query_as::<_, LoggedDeparture>(&format!(
+            "SELECT id, polled_at, {}
+             FROM departure_log
+             WHERE id > $1
+               AND ($2::text IS NULL OR global_route_id = $2 OR route_short_name = $2)
+             ORDER BY id
+             LIMIT $3",
+            DEPARTURE_COLUMNS
+        ))
+        .bind(after_id)
+        .bind(route)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query new departures")
+    }
+
+    /// Every logged departure polled in `[start, end]`, oldest poll first,
+    /// optionally limited to one route (global route ID or short name).
+    pub async fn departures_between(
+        &self,
+        start: i64,
+        end: i64,
+        route: Option<&str>,
+    ) -> Result<Vec<LoggedDeparture>> {
+        sqlx::query_as::<_, LoggedDeparture>(&format!(
+            "SELECT id, polled_at, {}
+             FROM departure_log
+             WHERE polled_at BETWEEN $1 AND $2
+               AND ($3::text IS NULL OR global_route_id = $3 OR route_short_name = $3)
+             ORDER BY polled_at, id",
+            DEPARTURE_COLUMNS
+        ))
+        .bind(start)
+        .bind(end)
+        .bind(route)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query departures in range")
+    }
+
+    /// Every logged row for one trip, oldest poll first and in stop order
+    /// within a poll.
+    pub async fn departures_for_trip(&self, rt_trip_id: &str) -> Result<Vec<LoggedDeparture>> {
+        sqlx::query_as::<_, LoggedDeparture>(&format!(
+            "SELECT id, polled_at, {}
+             FROM departure_log
+             WHERE rt_trip_id = $1
+             ORDER BY polled_at, departure_time, id",
+            DEPARTURE_COLUMNS
+        ))
+        .bind(rt_trip_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query departures for trip")
+    }
+
+    /// The trip's next stop as of the most recent poll that saw it: the
+    /// soonest departure logged in that poll.
+    pub async fn latest_for_trip(&self, rt_trip_id: &str) -> Result<Option<LoggedDeparture>> {
+        sqlx::query_as::<_, LoggedDeparture>(&format!(
+            "SELECT id, polled_at, {}
+             FROM departure_log
+             WHERE rt_trip_id = $1
+             ORDER BY polled_at DESC, departure_time, id
+             LIMIT 1",
+            DEPARTURE_COLUMNS
+        ))
+        .bind(rt_trip_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to query latest departure for trip")
+    }
+
+    /// Distinct trip IDs logged by polls after `since`, sorted.
+    pub async fn active_trips_since(&self, since: i64) -> Result<Vec<String>> {
+        sqlx::query_scalar(
+            "SELECT DISTINCT rt_trip_id
+             FROM departure_log
+             WHERE polled_at > $1 AND rt_trip_id IS NOT NULL
+             ORDER BY rt_trip_id",
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query active trips")
+    }
+
+    /// Real-time coverage of each poll in `[start, end]`, as
+    /// `(polled_at, fraction of departures with a real-time prediction)`.
+    pub async fn coverage_history(&self, start: i64, end: i64) -> Result<Vec<(FeedTime, f64)>> {
+        let rows = sqlx::query(
+            "SELECT polled_at,
+                    COUNT(*) FILTER (WHERE is_real_time)::float8 / COUNT(*) AS coverage
+             FROM departure_log
+             WHERE polled_at BETWEEN $1 AND $2
+             GROUP BY polled_at
+             ORDER BY polled_at",
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query coverage history")?;
+
+        Ok(rows.iter().map(|r| (r.get("polled_at"), r.get("coverage"))).collect())
+    }
+
+    /// How the predicted delay of one trip at one stop moved across polls, as
+    /// `(polled_at, delay_seconds)` oldest first. Polls where the trip had no
+    /// real-time prediction are left out.
+    pub async fn delay_history(&self, rt_trip_id: &str, stop_id: &str) -> Result<Vec<(FeedTime, i32)>> {
+        let rows = sqlx::query(
+            "SELECT polled_at, delay_seconds
+             FROM departure_log
+             WHERE rt_trip_id = $1 AND global_stop_id = $2 AND delay_seconds IS NOT NULL
+             ORDER BY polled_at",
+        )
+        .bind(rt_trip_id)
+        .bind(stop_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query delay history")?;
+
+        Ok(rows.iter().map(|r| (r.get("polled_at"), r.get("delay_seconds"))).collect())
+    }
+
+    /// Percentiles of absolute real-time delay for a route over polls in
+    /// `[start, end]`, as `(percentile, delay_seconds)` pairs. Percentiles are
+    /// fractions (0.5 = p50, 0.9 = p90). Empty when there's no delay data.
+    pub async fn delay_percentiles(
+        &self,
+        route_id: &str,
+        start: i64,
+        end: i64,
+        percentiles: &[f64],
+    ) -> Result<Vec<(f64, f64)>> {
+        if let Some(p) = percentiles.iter().find(|p| !(0.0..=1.0).contains(*p)) {
+            anyhow::bail!("Percentile {} is outside 0.0..=1.0", p);
+        }
+        if percentiles.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let values: Option<Vec<f64>> = sqlx::query_scalar(
+            "SELECT percentile_cont($4::float8[]) WITHIN GROUP (ORDER BY ABS(delay_seconds))
+             FROM departure_log
+             WHERE global_route_id = $1
+               AND polled_at BETWEEN $2 AND $3
+               AND is_real_time AND delay_seconds IS NOT NULL",
+        )
+        .bind(route_id)
+        .bind(start)
+        .bind(end)
+        .bind(percentiles)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to query delay percentiles")?;
+
+        Ok(values
+            .map(|v| percentiles.iter().copied().zip(v).collect())
+            .unwrap_or_default())
+    }
+
+    /// Mean real-time delay for a route over polls in `[start, end]`, bucketed
+    /// by the hour of the scheduled departure in `tz` (the agency's zone, so
+    /// rush hour lines up). Hours with no data are `None`. Like
+    /// `delay_percentiles`, every logged prediction counts, not just the last.
+    pub async fn avg_delay_by_hour(
+        &self,
+        route_id: &str,
+        start: i64,
+        end: i64,
+        tz: chrono_tz::Tz,
+    ) -> Result<[Option<f64>; 24]> {
+        let rows = sqlx::query(
+            "SELECT EXTRACT(HOUR FROM to_timestamp(scheduled_departure_time) AT TIME ZONE $4)::int AS hour,
+                    AVG(delay_seconds)::float8 AS mean
+             FROM departure_log
+             WHERE global_route_id = $1
+               AND polled_at BETWEEN $2 AND $3
+               AND is_real_time AND delay_seconds IS NOT NULL
+             GROUP BY hour",
+        )
+        .bind(route_id)
+        .bind(start)
+        .bind(end)
+        .bind(tz.name())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query delay by hour")?;
+
+        let mut hours = [None; 24];
+        for row in &rows {
+            let hour: i32 = row.get("hour");
+            hours[hour as usize] = Some(row.get("mean"));
+        }
+        Ok(hours)
+    }
+
+    /// Trips on a route with at least one cancelled stop in polls within
+    /// `[start, end]`, as `(rt_trip_id, cancelled stops)` worst first. A stop
+    /// counts once however many polls reported it cancelled.
+    ///
+    /// The feed has no per-stop SKIPPED status; a cancelled departure at a
+    /// stop is the equivalent signal.
+    pub async fn trips_with_cancellations(
+        &self,
+        route_id: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<(String, i64)>> {
+        let rows = sqlx::query(
+            "SELECT rt_trip_id, COUNT(DISTINCT global_stop_id) AS cancelled_stops
+             FROM departure_log
+             WHERE global_route_id = $1
+               AND polled_at BETWEEN $2 AND $3
+               AND is_cancelled AND rt_trip_id IS NOT NULL
+             GROUP BY rt_trip_id
+             ORDER BY cancelled_stops DESC, rt_trip_id",
+        )
+        .bind(route_id)
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query cancelled trips")?;
+
+        Ok(rows.iter().map(|r| (r.get("rt_trip_id"), r.get("cancelled_stops"))).collect())
+    }
+
+    /// On-time performance for a route over polls in `[start, end]`, judged on
+    /// the last prediction logged for each trip at each stop (the closest we
+    /// get to the actual departure). A trip runs again every service day under
+    /// the same ID, so each day's run is judged on its own, told apart by
+    /// scheduled time. Tolerances are inclusive, as in
+    /// `analysis::on_time_performance`.
+    pub async fn on_time_performance(
+        &self,
+        route_id: &str,
+        start: i64,
+        end: i64,
+        early_tol_secs: i32,
+        late_tol_secs: i32,
+    ) -> Result<OnTimePerformance> {
+        let row = sqlx::query(
+            "WITH final AS (
+                SELECT DISTINCT ON (rt_trip_id, global_stop_id, scheduled_departure_time) delay_seconds
+                FROM departure_log
+                WHERE global_route_id = $1 AND is_real_time AND NOT is_cancelled
+                  AND rt_trip_id IS NOT NULL AND delay_seconds IS NOT NULL
+                  AND polled_at BETWEEN $2 AND $3
+                ORDER BY rt_trip_id, global_stop_id, scheduled_departure_time, polled_at DESC
+             )
+             SELECT COUNT(*) FILTER (WHERE delay_seconds BETWEEN $4 AND $5) AS on_time,
+                    COUNT(*) AS samples
+             FROM final",
+        )
+        .bind(route_id)
+        .bind(start)
+        .bind(end)
+        .bind(-early_tol_secs.saturating_abs())
+        .bind(late_tol_secs.saturating_abs())
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to query on-time performance")?;
+
+        let on_time: i64 = row.get("on_time");
+        let samples: i64 = row.get("samples");
+        Ok(OnTimePerformance { on_time: on_time as usize, samples: samples as usize })
+    }
+
+    /// How far earlier predictions for a route landed from the final observed
+    /// departure time, over polls in `[start, end]`. Each service day's run of
+    /// a trip is scored separately; trip IDs repeat daily, so runs are told
+    /// apart by scheduled time as in `PollResult::diff`.
+    pub async fn prediction_accuracy(
+        &self,
+        route_id: &str,
+        start: i64,
+        end: i64,
+        window: &AccuracyWindow,
+    ) -> Result<AccuracyStats> {
+        let errors: Vec<i64> = sqlx::query_scalar(
+            "WITH final AS (
+                SELECT DISTINCT ON (rt_trip_id, global_stop_id, scheduled_departure_time)
+                       rt_trip_id, global_stop_id, scheduled_departure_time, polled_at, departure_time
+                FROM departure_log
+                WHERE global_route_id = $1 AND is_real_time AND NOT is_cancelled
+                  AND rt_trip_id IS NOT NULL
+                  AND polled_at BETWEEN $2 AND $3
+                ORDER BY rt_trip_id, global_stop_id, scheduled_departure_time, polled_at DESC
+             )
+             SELECT ABS(p.departure_time - f.departure_time)
+             FROM final f
+             JOIN departure_log p USING (rt_trip_id, global_stop_id, scheduled_departure_time)
+             WHERE f.departure_time - f.polled_at BETWEEN 0 AND $4
+               AND p.global_route_id = $1 AND p.is_real_time AND NOT p.is_cancelled
+               AND p.polled_at BETWEEN $2 AND $3
+               AND p.polled_at < f.polled_at
+               AND f.departure_time - p.polled_at <= $5",
+        )
+        .bind(route_id)
+        .bind(start)
+        .bind(end)
+        .bind(window.match_window_secs)
+        .bind(window.horizon_secs)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query prediction accuracy")?;
+
+        Ok(AccuracyStats {
+            samples: errors.len(),
+            mean_abs_error_secs: (!errors.is_empty())
+                .then(|| errors.iter().sum::<i64>() as f64 / errors.len() as f64),
+            max_abs_error_secs: errors.iter().copied().max(),
+        })
+    }
+
+    /// Stream departure_log (optionally one route) to `writer` as CSV with a
+    /// header row, without loading the table into memory. Returns the number
+    /// of records written.
+    pub async fn export_csv(&self, writer: impl std::io::Write, route: Option<&str>) -> Result<usize> {
+        let mut out = csv::Writer::from_writer(writer);
+        out.write_record([
+            "id",
+            "polled_at",
+            "global_stop_id",
+            "global_route_id",
+            "route_short_name",
+            "headsign",
+            "departure_time",
+            "scheduled_departure_time",
+            "delay_seconds",
+            "is_real_time",
+            "is_cancelled",
+            "rt_trip_id",
+        ])?;
+
+        let query = format!(
+            "SELECT id, polled_at, {}
+             FROM departure_log
+             WHERE ($1::text IS NULL OR global_route_id = $1 OR route_short_name = $1)
+             ORDER BY id",
+            DEPARTURE_COLUMNS
+        );
+        let mut rows = sqlx::query_as::<_, LoggedDeparture>(&query)
+            .bind(route)
+            .fetch(&self.pool);
+
+        let mut written = 0;
+        while let Some(row) = rows.try_next().await.context("Failed to read departure_log")? {
+            let d = &row.departure;
+            out.write_record([
+                row.id.to_string(),
+                row.polled_at.to_string(),
+                d.global_stop_id.clone(),
+                d.global_route_id.clone(),
+                d.route_short_name.clone(),
+                d.headsign.clone().unwrap_or_default(),
+                d.departure_time.to_string(),
+                d.scheduled_departure_time.to_string(),
+                d.delay_seconds.map(|s| s.to_string()).unwrap_or_default(),
+                d.is_real_time.to_string(),
+                d.is_cancelled.to_string(),
+                d.rt_trip_id.clone().unwrap_or_default(),
+            ])?;
+            written += 1;
+        }
+        out.flush().context("Failed to flush CSV output")?;
+        Ok(written)
+    }
+
+    /// Stops as a GeoJSON `FeatureCollection` of `Point`s (`[lon, lat]` per
+    /// the spec), each carrying its logged departure count and mean delay.
+    /// With `route`, only stops served by that route are included.
+    pub async fn export_geojson(&self, route: Option<&str>) -> Result<String> {
+        let rows = sqlx::query(
+            "SELECT s.global_stop_id, s.stop_name, s.lat, s.lon,
+                    COUNT(d.id) AS departures,
+                    AVG(d.delay_seconds)::float8 AS mean_delay_seconds
+             FROM stops s
+             JOIN departure_log d ON d.global_stop_id = s.global_stop_id
+             WHERE ($1::text IS NULL OR d.global_route_id = $1 OR d.route_short_name = $1)
+             GROUP BY s.global_stop_id, s.stop_name, s.lat, s.lon
+             ORDER BY s.global_stop_id",
+        )
+        .bind(route)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to query stops for GeoJSON")?;
+
+        let features: Vec<_> = rows
+            .iter()
+            .filter(|r| r.get::<f64, _>("lat") != 0.0 || r.get::<f64, _>("lon") != 0.0)
+            .map(|r| {
+                serde_json::json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [r.get::<f64, _>("lon"), r.get::<f64, _>("lat")],
+                    },
+                    "properties": {
+                        "global_stop_id": r.get::<String, _>("global_stop_id"),
+                        "stop_name": r.get::<String, _>("stop_name"),
+                        "departures": r.get::<i64, _>("departures"),
+                        "mean_delay_seconds": r.get::<Option<f64>, _>("mean_delay_seconds"),
+                    },
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({ "type": "FeatureCollection", "features": features }).to_string())
+    }
+
+    /// Delete departure_log rows polled before `cutoff` (Unix seconds).
+    /// Returns the number of rows removed.
+    pub async fn prune_older_than(&self, cutoff: i64) -> Result<u64> {
+        let mut tx = self.pool.begin().await.context("Failed to start transaction")?;
+        let deleted = sqlx::query("DELETE FROM departure_log WHERE polled_at < $1")
+            .bind(cutoff)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to prune departure_log")?
+            .rows_affected();
+        tx.commit().await.context("Failed to commit prune")?;
+
+        tracing::info!(deleted, cutoff, "Pruned departure_log");
+        Ok(deleted)
+    }
+
+    /// Keep only the last `days` days of departure_log.
+    pub async fn prune_keeping_days(&self, days: i64) -> Result<u64> {
+        let cutoff = FeedTime::now().secs() - days * 86_400;
+        self.prune_older_than(cutoff).await
+    }
+
+    /// Log one poll's departures. Duplicates of rows already logged (same
+    /// poll time, stop, route, scheduled time and trip) are skipped; returns
+    /// how many rows were actually inserted.
     pub async fn insert_departure_log(
         &self,
-        polled_at: i64,
+        polled_at: FeedTime,
         departures: &[Departure],
     ) -> Result<usize> {
-        if departures.is_empty() {
-            return Ok(0);
-        }
+        self.insert_departure_log_chunked(polled_at, departures, DEFAULT_INSERT_CHUNK_SIZE)
+            .await
+    }
 
-        let mut tx = self.pool.begin().await.context("Failed to start transaction")?;
+    /// `insert_departure_log` with multi-row INSERTs of up to `chunk_size`
+    /// rows, each committed on its own. A failure partway leaves earlier
+    /// chunks in place; retrying is safe since duplicates are skipped.
+    pub async fn insert_departure_log_chunked(
+        &self,
+        polled_at: FeedTime,
+        departures: &[Departure],
+        chunk_size: usize,
+    ) -> Result<usize> {
+        let rows: Vec<(FeedTime, &Departure)> = departures.iter().map(|d| (polled_at, d)).collect();
+        Ok(self.insert_rows(&rows, chunk_size).await?)
+    }
 
-        for dep in departures {
-            sqlx::query(
+    async fn insert_rows(
+        &self,
+        rows: &[(FeedTime, &Departure)],
+        chunk_size: usize,
+    ) -> std::result::Result<usize, InsertError> {
+        let mut inserted = 0;
+        let mut committed = 0;
+        for chunk in rows.chunks(chunk_size.clamp(1, MAX_INSERT_CHUNK_SIZE)) {
+            let mut query = QueryBuilder::<Postgres>::new(
                 "INSERT INTO departure_log (
                     polled_at, global_stop_id, global_route_id, route_short_name, headsign,
                     departure_time, scheduled_departure_time, delay_seconds,
                     is_real_time, is_cancelled, rt_trip_id
-                ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
-            )
-            .bind(polled_at)
-            .bind(&dep.global_stop_id)
-            .bind(&dep.global_route_id)
-            .bind(&dep.route_short_name)
-            .bind(&dep.headsign)
-            .bind(dep.departure_time)
-            .bind(dep.scheduled_departure_time)
-            .bind(dep.delay_seconds)
-            .bind(dep.is_real_time)
-            .bind(dep.is_cancelled)
-            .bind(&dep.rt_trip_id)
-            .execute(&mut *tx)
-            .await
-            .context("Failed to insert departure")?;
+                ) ",
+            );
+            query.push_values(chunk, |mut row, (polled_at, dep)| {
+                row.push_bind(*polled_at)
+                    .push_bind(&dep.global_stop_id)
+                    .push_bind(&dep.global_route_id)
+                    .push_bind(&dep.route_short_name)
+                    .push_bind(&dep.headsign)
+                    .push_bind(dep.departure_time)
+                    .push_bind(dep.scheduled_departure_time)
+                    .push_bind(dep.delay_seconds)
+                    .push_bind(dep.is_real_time)
+                    .push_bind(dep.is_cancelled)
+                    .push_bind(&dep.rt_trip_id);
+            });
+            query.push(" ON CONFLICT DO NOTHING");
+
+            match query.build().execute(&self.pool).await {
+                Ok(done) => inserted += done.rows_affected() as usize,
+                Err(source) => return Err(InsertError { committed, inserted, source }),
+            }
+            committed += chunk.len();
+        }
+        Ok(inserted)
+    }
+}
+
+/// A chunked insert that failed partway. Chunks before the failing one are
+/// already committed.
+#[derive(Debug)]
+pub struct InsertError {
+    /// Leading input rows whose chunks were committed.
+    pub committed: usize,
+    /// Rows actually written from those chunks, less duplicates.
+    pub inserted: usize,
+    pub source: sqlx::Error,
+}
+
+impl std::fmt::Display for InsertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to insert departures ({} committed before the failure)", self.inserted)
+    }
+}
+
+impl std::error::Error for InsertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Collects departures across polls and writes them in one batch once
+/// `max_rows` have built up or the oldest buffered poll is `max_age` old.
+///
+/// Thresholds are only checked on `push`, and nothing is written on drop:
+/// call `flush` before shutting down or buffered rows are lost. While writes
+/// keep failing at most `max_buffered` rows are held, dropping the oldest.
+pub struct BufferedWriter<'a> {
+    db: &'a Database,
+    rows: Vec<(FeedTime, Departure)>,
+    max_rows: usize,
+    max_age: Duration,
+    max_buffered: usize,
+    chunk_size: usize,
+    oldest: Option<Instant>,
+}
+
+impl<'a> BufferedWriter<'a> {
+    pub fn new(db: &'a Database, max_rows: usize, max_age: Duration) -> Self {
+        Self {
+            db,
+            rows: Vec::new(),
+            max_rows,
+            max_age,
+            max_buffered: DEFAULT_WRITE_BUFFER_CAP,
+            chunk_size: DEFAULT_INSERT_CHUNK_SIZE,
+            oldest: None,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Rows to hold while writes fail. Never less than `max_rows`.
+    pub fn with_max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered;
+        self
+    }
+
+    /// Rows waiting to be written.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Buffer one poll, flushing if a threshold is reached. Returns the rows
+    /// inserted by that flush, or 0 if nothing was written.
+    pub async fn push(
+        &mut self,
+        polled_at: FeedTime,
+        departures: &[Departure],
+    ) -> std::result::Result<usize, InsertError> {
+        let now = Instant::now();
+        let oldest = *self.oldest.get_or_insert(now);
+        self.rows.extend(departures.iter().map(|d| (polled_at, d.clone())));
+
+        let flushed = if self.rows.len() >= self.max_rows || now.duration_since(oldest) >= self.max_age {
+            self.flush().await
+        } else {
+            Ok(0)
+        };
+        let cap = self.max_buffered.max(self.max_rows);
+        if self.rows.len() > cap {
+            let dropped = self.rows.len() - cap;
+            self.rows.drain(..dropped);
+            tracing::warn!(dropped, "Write buffer full, dropped the oldest departures");
         }
+        flushed
+    }
 
-        tx.commit().await.context("Failed to commit departure log")?;
-        Ok(departures.len())
+    /// Write everything buffered. On error the rows already committed are
+    /// removed and the rest stay buffered for the next attempt.
+    pub async fn flush(&mut self) -> std::result::Result<usize, InsertError> {
+        if self.rows.is_empty() {
+            return Ok(0);
+        }
+        let rows: Vec<(FeedTime, &Departure)> = self.rows.iter().map(|(t, d)| (*t, d)).collect();
+        let inserted = match self.db.insert_rows(&rows, self.chunk_size).await {
+            Ok(inserted) => inserted,
+            Err(e) => {
+                self.rows.drain(..e.committed);
+                return Err(e);
+            }
+        };
+        tracing::debug!(buffered = self.rows.len(), inserted, "Flushed departure buffer");
+        if inserted < self.rows.len() {
+            tracing::info!(skipped = self.rows.len() - inserted, "Skipped duplicate departures");
+        }
+        self.rows.clear();
+        self.oldest = None;
+        Ok(inserted)
     }
 }
@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use anyhow::Result;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::models::Departure;
+
+/// How route IDs are tidied before comparing, for feeds that report one route
+/// several ways (" 7", "07", "Rapid 7"). Every rule is off by default, so the
+/// default leaves IDs untouched and matching stays exact.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteNormalization {
+    /// Strip surrounding whitespace
+    #[serde(default)]
+    pub trim: bool,
+    /// Compare case-insensitively ("r7" == "R7")
+    #[serde(default)]
+    pub case_fold: bool,
+    /// Drop the first of these that the ID starts with, e.g. `"Rapid "`
+    #[serde(default)]
+    pub strip_prefixes: Vec<String>,
+    /// "007" becomes "7"
+    #[serde(default)]
+    pub strip_leading_zeros: bool,
+}
+
+impl RouteNormalization {
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Rejects rules that would erase IDs outright.
+    pub fn validate(&self) -> Result<()> {
+        if self.strip_prefixes.iter().any(|p| p.trim().is_empty()) {
+            anyhow::bail!("route normalization strip_prefixes can't contain an empty prefix");
+        }
+        Ok(())
+    }
+}
+
+/// `id` with `rule` applied: trim, case-fold, strip a prefix, then strip
+/// leading zeros. A prefix is only stripped if something follows it and "000"
+/// becomes "0", so a non-empty ID never normalizes to an empty one.
+pub fn normalize_route_id(id: &str, rule: &RouteNormalization) -> String {
+    let mut id = if rule.trim { id.trim().to_string() } else { id.to_string() };
+    if rule.case_fold {
+        id = id.to_lowercase();
+    }
+    for prefix in &rule.strip_prefixes {
+        let prefix = if rule.case_fold { prefix.to_lowercase() } else { prefix.clone() };
+        if let Some(rest) = id.strip_prefix(&prefix) {
+            let rest = if rule.trim { rest.trim() } else { rest };
+            if !rest.is_empty() {
+                id = rest.to_string();
+                break;
+            }
+        }
+    }
+    if rule.strip_leading_zeros && id.len() > 1 {
+        let rest = id.trim_start_matches('0');
+        id = if rest.is_empty() { "0".to_string() } else { rest.to_string() };
+    }
+    id
+}
+
+/// Which raw IDs collapse into each normalized ID under `rule`, for auditing
+/// a normalization before relying on it.
+pub fn route_id_mapping<'a>(
+    ids: impl IntoIterator<Item = &'a str>,
+    rule: &RouteNormalization,
+) -> BTreeMap<String, BTreeSet<String>> {
+    let mut mapping: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for id in ids {
+        mapping.entry(normalize_route_id(id, rule)).or_default().insert(id.to_string());
+    }
+    mapping
+}
+
+/// How requested routes are compared against a departure's `route_short_name`.
+///
+/// Agencies suffix variants ("1", "1R", "R1"), so `Prefix` and `Pattern` let a
+/// caller capture every Route 1 variant at once. `Exact` is the default;
+/// `Normalized` is `Exact` for feeds with inconsistent ID formatting.
+#[derive(Debug, Clone)]
+pub enum RouteMatcher {
+    Exact(Vec<String>),
+    Prefix(Vec<String>),
+    Pattern(Regex),
+    /// `Exact`, comparing normalized forms on both sides
+    Normalized(Vec<String>, RouteNormalization),
+}
+
+impl RouteMatcher {
+    pub fn matches(&self, route_short_name: &str) -> bool {
+        match self {
+            RouteMatcher::Exact(routes) => routes.iter().any(|r| r == route_short_name),
+            RouteMatcher::Prefix(prefixes) => {
+                prefixes.iter().any(|p| route_short_name.starts_with(p.as_str()))
+            }
+            RouteMatcher::Pattern(re) => re.is_match(route_short_name),
+            RouteMatcher::Normalized(routes, rule) => {
+                let name = normalize_route_id(route_short_name, rule);
+                routes.iter().any(|r| normalize_route_id(r, rule) == name)
+            }
+        }
+    }
+
+    /// `Exact` under `rule`, or plain `Exact` when `rule` is a no-op.
+    pub fn exact(routes: Vec<String>, rule: &RouteNormalization) -> Self {
+        if rule.is_noop() {
+            RouteMatcher::Exact(routes)
+        } else {
+            RouteMatcher::Normalized(routes, rule.clone())
+        }
+    }
+
+    pub fn matches_departure(&self, departure: &Departure) -> bool {
+        self.matches(&departure.route_short_name)
+    }
+}
+
+/// Narrows a set of departures. Each field left empty places no constraint;
+/// the ones that are set must all match (AND), so `DepartureFilter::default()`
+/// keeps everything.
+///
+/// The Transit API has no direction or vehicle IDs, so `headsigns` stands in
+/// for direction and `trip_ids` for following one bus.
+#[derive(Debug, Clone, Default)]
+pub struct DepartureFilter {
+    pub stop_ids: Vec<String>,
+    pub routes: Option<RouteMatcher>,
+    /// Compared case-insensitively
+    pub headsigns: Vec<String>,
+    pub trip_ids: Vec<String>,
+}
+
+impl DepartureFilter {
+    /// A filter on route alone.
+    pub fn routes(routes: RouteMatcher) -> Self {
+        Self { routes: Some(routes), ..Self::default() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stop_ids.is_empty()
+            && self.routes.is_none()
+            && self.headsigns.is_empty()
+            && self.trip_ids.is_empty()
+    }
+
+    pub fn matches(&self, departure: &Departure) -> bool {
+        let stop_ok = self.stop_ids.is_empty() || self.stop_ids.contains(&departure.global_stop_id);
+        let route_ok = self.routes.as_ref().is_none_or(|m| m.matches_departure(departure));
+        let headsign_ok = self.headsigns.is_empty()
+            || departure
+                .headsign
+                .as_deref()
+                .is_some_and(|h| self.headsigns.iter().any(|want| want.eq_ignore_ascii_case(h)));
+        let trip_ok = self.trip_ids.is_empty()
+            || departure.rt_trip_id.as_ref().is_some_and(|t| self.trip_ids.contains(t));
+        stop_ok && route_ok && headsign_ok && trip_ok
+    }
+}
+
+/// What a poll collects: the stops to request, and which of the departures
+/// they return to keep. A stop serves every route through it, so without
+/// `route_ids` a route dropped from the config would still be logged at
+/// stops it shares with the others.
+#[derive(Debug, Clone, Default)]
+pub struct PollTargets {
+    pub stop_ids: Vec<String>,
+    /// Global route IDs kept at every stop. Empty keeps every route.
+    pub route_ids: HashSet<String>,
+    /// Watch-point stops where every route is kept
+    pub watch_stop_ids: HashSet<String>,
+}
+
+impl PollTargets {
+    pub fn keeps(&self, departure: &Departure) -> bool {
+        self.route_ids.is_empty()
+            || self.route_ids.contains(&departure.global_route_id)
+            || self.watch_stop_ids.contains(&departure.global_stop_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIXED: [&str; 7] = ["1", "1R", "R1", "10", "12", "R12", "2"];
+
+    fn matching(matcher: &RouteMatcher) -> Vec<&'static str> {
+        MIXED.into_iter().filter(|r| matcher.matches(r)).collect()
+    }
+
+    #[test]
+    fn pattern_matches_route_variants() {
+        let route_1 = RouteMatcher::Pattern(Regex::new(r"^R?1R?$").unwrap());
+        assert_eq!(matching(&route_1), ["1", "1R", "R1"]);
+
+        // Unanchored patterns match anywhere in the name
+        let any_rapid = RouteMatcher::Pattern(Regex::new("R").unwrap());
+        assert_eq!(matching(&any_rapid), ["1R", "R1", "R12"]);
+
+        let nothing = RouteMatcher::Pattern(Regex::new("^7$").unwrap());
+        assert!(matching(&nothing).is_empty());
+    }
+
+    #[test]
+    fn exact_and_prefix_over_mixed_ids() {
+        assert_eq!(matching(&RouteMatcher::Exact(vec!["1".into()])), ["1"]);
+        assert_eq!(matching(&RouteMatcher::Prefix(vec!["1".into()])), ["1", "1R", "10", "12"]);
+        assert_eq!(matching(&RouteMatcher::Prefix(vec!["R".into(), "2".into()])), ["R1", "R12", "2"]);
+    }
+
+    #[test]
+    fn poll_targets_keep_configured_routes_and_watch_points() {
+        let at = |stop: &str, route: &str| Departure::sample().stop(stop).route(route, "1");
+        let targets = PollTargets {
+            stop_ids: vec!["BBB:7023".into(), "BBB:1234".into()],
+            route_ids: HashSet::from(["BBB:14412".into()]),
+            watch_stop_ids: HashSet::from(["BBB:1234".into()]),
+        };
+        assert!(targets.keeps(&at("BBB:7023", "BBB:14412")));
+        // A route dropped from the config, at a stop it shares with route 1
+        assert!(!targets.keeps(&at("BBB:7023", "BBB:14430")));
+        // Every route counts at a watch point
+        assert!(targets.keeps(&at("BBB:1234", "BBB:14430")));
+
+        assert!(PollTargets::default().keeps(&at("BBB:7023", "BBB:14430")));
+    }
+
+    /// Route 1 and 1R in both directions at two stops: trips 1001..=1004, and
+    /// one departure with neither a headsign nor a trip ID.
+    fn board() -> Vec<Departure> {
+        vec![
+            Departure::sample().trip("1001"),
+            Departure::sample().headsign("Downtown SM").trip("1002"),
+            Departure::sample().route("BBB:14413", "1R").trip("1003"),
+            Departure::sample().stop("BBB:7024").trip("1004"),
+            Departure::sample().stop("BBB:7024").headsign(None),
+        ]
+    }
+
+    /// Trip IDs of the `board` departures `filter` keeps, "-" for none.
+    fn trips(filter: &DepartureFilter) -> Vec<String> {
+        board()
+            .into_iter()
+            .filter(|d| filter.matches(d))
+            .map(|d| d.rt_trip_id.unwrap_or_else(|| "-".to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn empty_filter_keeps_everything() {
+        let filter = DepartureFilter::default();
+        assert!(filter.is_empty());
+        assert_eq!(trips(&filter), ["1001", "1002", "1003", "1004", "-"]);
+        assert!(!DepartureFilter::routes(RouteMatcher::Exact(vec!["1".into()])).is_empty());
+    }
+
+    #[test]
+    fn overlapping_filters_must_all_match() {
+        let ucla = DepartureFilter { headsigns: vec!["ucla".into()], ..Default::default() };
+        assert_eq!(trips(&ucla), ["1001", "1003", "1004"]);
+
+        // Route and headsign overlap on 1001 and 1004; the stop narrows to 1001
+        let route_1 = Some(RouteMatcher::Exact(vec!["1".into()]));
+        let route_1_ucla = DepartureFilter { routes: route_1, ..ucla.clone() };
+        assert_eq!(trips(&route_1_ucla), ["1001", "1004"]);
+        let at_7023 = DepartureFilter { stop_ids: vec!["BBB:7023".into()], ..route_1_ucla };
+        assert_eq!(trips(&at_7023), ["1001"]);
+
+        // A prefix takes in 1R too; trip IDs then pick one of each
+        let any_1 = DepartureFilter {
+            routes: Some(RouteMatcher::Prefix(vec!["1".into()])),
+            trip_ids: vec!["1002".into(), "1003".into()],
+            ..Default::default()
+        };
+        assert_eq!(trips(&any_1), ["1002", "1003"]);
+
+        // Constraints that don't overlap match nothing
+        let disjoint = DepartureFilter { trip_ids: vec!["1002".into()], ..ucla };
+        assert!(trips(&disjoint).is_empty());
+    }
+}
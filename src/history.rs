@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+
+use crate::models::PollResult;
+
+pub const DEFAULT_HISTORY_SIZE: usize = 10;
+
+/// Ring buffer of the most recent polls, so the server can show short-term
+/// history and compare against the previous poll without touching the DB.
+#[derive(Debug, Clone)]
+pub struct PollHistory {
+    capacity: usize,
+    polls: VecDeque<PollResult>,
+    stale: bool,
+}
+
+impl PollHistory {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { capacity, polls: VecDeque::with_capacity(capacity), stale: false }
+    }
+
+    pub fn push(&mut self, poll: PollResult) {
+        if self.polls.len() == self.capacity {
+            self.polls.pop_front();
+        }
+        self.polls.push_back(poll);
+        self.stale = false;
+    }
+
+    /// Push a poll restored from disk; `is_stale` stays true until the next
+    /// fresh poll arrives via `push`.
+    pub fn push_stale(&mut self, poll: PollResult) {
+        self.push(poll);
+        self.stale = true;
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub fn latest(&self) -> Option<&PollResult> {
+        self.polls.back()
+    }
+
+    /// The poll before `latest`, if any.
+    pub fn previous(&self) -> Option<&PollResult> {
+        self.polls.iter().rev().nth(1)
+    }
+
+    /// Up to `n` polls, newest first.
+    pub fn recent(&self, n: usize) -> Vec<&PollResult> {
+        self.polls.iter().rev().take(n).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.polls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polls.is_empty()
+    }
+}
+
+impl Default for PollHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FeedTime;
+
+    /// An empty poll, told apart by when it was taken.
+    fn poll(at: i64) -> PollResult {
+        PollResult { polled_at: FeedTime::from_secs(at), departures: Vec::new() }
+    }
+
+    fn polled_at(polls: &[&PollResult]) -> Vec<i64> {
+        polls.iter().map(|p| p.polled_at.secs()).collect()
+    }
+
+    #[test]
+    fn oldest_poll_is_evicted_at_capacity() {
+        let mut history = PollHistory::new(3);
+        for at in 1..=4 {
+            history.push(poll(at));
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(polled_at(&history.recent(10)), [4, 3, 2]);
+    }
+
+    #[test]
+    fn zero_capacity_keeps_one_poll() {
+        let mut history = PollHistory::new(0);
+        assert!(history.is_empty());
+        history.push(poll(1));
+        history.push(poll(2));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.latest().unwrap().polled_at.secs(), 2);
+    }
+
+    #[test]
+    fn previous_needs_two_polls() {
+        let mut history = PollHistory::default();
+        assert!(history.latest().is_none());
+        history.push(poll(1));
+        assert_eq!(history.latest().unwrap().polled_at.secs(), 1);
+        assert!(history.previous().is_none());
+
+        history.push(poll(2));
+        assert_eq!(history.latest().unwrap().polled_at.secs(), 2);
+        assert_eq!(history.previous().unwrap().polled_at.secs(), 1);
+    }
+
+    #[test]
+    fn recent_is_newest_first() {
+        let mut history = PollHistory::default();
+        for at in 1..=5 {
+            history.push(poll(at));
+        }
+        assert_eq!(polled_at(&history.recent(3)), [5, 4, 3]);
+        assert_eq!(polled_at(&history.recent(10)), [5, 4, 3, 2, 1]);
+        assert!(history.recent(0).is_empty());
+    }
+
+    #[test]
+    fn restored_poll_is_stale_until_the_next_push() {
+        let mut history = PollHistory::default();
+        assert!(!history.is_stale());
+        history.push_stale(poll(1));
+        assert!(history.is_stale());
+        assert_eq!(history.latest().unwrap().polled_at.secs(), 1);
+
+        history.push(poll(2));
+        assert!(!history.is_stale());
+        assert_eq!(history.previous().unwrap().polled_at.secs(), 1);
+    }
+}
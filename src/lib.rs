@@ -1,40 +1,136 @@
+//! Big Blue Bus departure tracking: a Transit API client, the Postgres
+//! departure log, and analysis over both. The `bigbluebunch` CLI and the
+//! `server` binary are thin drivers over this crate.
+//!
+//! The common types are re-exported at the root; the modules hold the rest.
+
+pub mod analysis;
+pub mod archive;
 pub mod api;
+#[cfg(feature = "wire")]
 pub mod api_server;
+pub mod breaker;
+pub mod bunching;
 pub mod db;
+pub mod filter;
+pub mod history;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod models;
+pub mod notify;
+pub mod snapshot;
+pub mod source;
+#[cfg(feature = "wire")]
+pub mod wire;
+
+pub use analysis::{DelayStats, DelaySummary, LateTrip, OnTimePerformance};
+pub use api::{parse_file, parse_stop_departures, ParseOptions, RetryPolicy, TransitClient};
+pub use archive::FeedArchive;
+pub use bunching::{detect_bunching, BunchingEvent};
+pub use db::{BufferedWriter, Database};
+pub use filter::{normalize_route_id, DepartureFilter, PollTargets, RouteMatcher, RouteNormalization};
+pub use models::{format_delay, Departure, FeedTime, LoggedDeparture, PollDiff, PollResult, Stop};
+pub use notify::{MonitorEvent, Notifier, WebhookNotifier};
+pub use source::{DepartureSource, FileDepartureSource, StaticDepartureSource};
 
+use std::collections::HashSet;
+use std::str::FromStr;
 use std::time::Duration;
-use api::TransitClient;
-use db::Database;
-use models::PollResult;
 
-pub async fn poll_once(
-    client: &TransitClient,
-    db: &Database,
-    stop_ids: &[String],
+/// Log output style, chosen with `LOG_FORMAT` / `--log-format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines (the default)
+    #[default]
+    Pretty,
+    /// One JSON object per event, fields at the top level, for Loki/Elasticsearch
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => anyhow::bail!("log format must be pretty or json, got {:?}", s),
+        }
+    }
+}
+
+/// Install the global tracing subscriber. `RUST_LOG` sets the filter
+/// (default: info).
+pub fn init_tracing(format: LogFormat) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+    match format {
+        LogFormat::Pretty => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_env_filter(filter)
+            .init(),
+    }
+}
+
+/// Fetch every stop in `targets` (100 per call, 13 s apart with
+/// `rate_limit`) and drop the departures `targets` doesn't keep. `None` if
+/// any call fails. Nothing is written; see `poll_once`.
+pub async fn fetch_poll<C: DepartureSource>(
+    client: &C,
+    targets: &PollTargets,
     rate_limit: bool,
 ) -> Option<PollResult> {
-    let polled_at = chrono::Utc::now().timestamp();
+    let polled_at = FeedTime::now();
     let mut all_departures = Vec::new();
 
-    for (i, chunk) in stop_ids.chunks(100).enumerate() {
+    for (i, chunk) in targets.stop_ids.chunks(100).enumerate() {
         if i > 0 && rate_limit {
             tokio::time::sleep(Duration::from_secs(13)).await;
         }
-        match client.fetch_stop_departures(chunk).await {
+        let chunk: Vec<&str> = chunk.iter().map(String::as_str).collect();
+        match client.fetch_departures(&chunk).await {
             Ok(deps) => all_departures.extend(deps),
             Err(e) => {
                 tracing::error!(error = %e, "Departures poll failed");
+                #[cfg(feature = "metrics")]
+                metrics::record_poll_error();
                 return None;
             }
         }
     }
 
-    let count = all_departures.len();
-    if let Err(e) = db.insert_departure_log(polled_at, &all_departures).await {
-        tracing::error!(error = %e, "Failed to persist departures");
-    }
+    all_departures.retain(|d| targets.keeps(d));
+    let result = PollResult { polled_at, departures: all_departures };
+    #[cfg(feature = "metrics")]
+    metrics::record_poll(&result);
+    let routes = result.departures.iter().map(|d| &d.global_route_id).collect::<HashSet<_>>().len();
+    tracing::info!(
+        departures = result.departures.len(),
+        routes,
+        realtime_pct = (result.realtime_coverage() * 100.0).round() as u32,
+        avg_delay_secs = DelaySummary::from_departures(&result.departures).map(|s| s.mean.round() as i64),
+        "Poll complete"
+    );
+    Some(result)
+}
 
-    tracing::info!(departures = count, "Poll complete");
-    Some(PollResult { polled_at, departures: all_departures })
+/// `fetch_poll`, then log the departures straight to `db`.
+pub async fn poll_once<C: DepartureSource>(
+    client: &C,
+    db: &Database,
+    targets: &PollTargets,
+    rate_limit: bool,
+) -> Option<PollResult> {
+    let result = fetch_poll(client, targets, rate_limit).await?;
+    let count = result.departures.len();
+    match db.insert_departure_log(result.polled_at, &result.departures).await {
+        Ok(inserted) if inserted < count => {
+            tracing::info!(skipped = count - inserted, "Skipped duplicate departures");
+        }
+        Ok(_) => {}
+        Err(e) => tracing::error!(error = %e, "Failed to persist departures"),
+    }
+    Some(result)
 }
@@ -1,23 +1,37 @@
-use anyhow::Result;
-use bigbluebunch::api::TransitClient;
+use anyhow::{Context, Result};
+use bigbluebunch::{
+    analysis::{
+        average_delay_by_route, delay_histogram, render_histogram, DEFAULT_OTP_EARLY_SECS,
+        DEFAULT_OTP_LATE_SECS,
+    },
+    api::{self, TransitClient},
+    archive,
+    bunching::{bunching_across_polls, DEFAULT_BUNCHING_THRESHOLD_SECS},
+    db::Database,
+    init_tracing,
+    models::{format_delay, Departure, FeedTime, LoggedDeparture, PollResult},
+    LogFormat,
+};
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .init();
-
     dotenvy::dotenv().ok();
 
+    let log_format: LogFormat = match std::env::var("LOG_FORMAT") {
+        Ok(format) => format.parse()?,
+        Err(_) => LogFormat::default(),
+    };
+    init_tracing(log_format);
+
     let args: Vec<String> = std::env::args().collect();
 
     // --discover: find BBB Route 1's global_route_id near UCLA.
     if args.contains(&"--discover".to_string()) {
-        let api_key = std::env::var("TRANSIT_API_KEY").expect("TRANSIT_API_KEY must be set");
-        TransitClient::new(api_key).discover_route_id().await?;
+        TransitClient::from_env().discover_route_id().await?;
         return Ok(());
     }
 
@@ -35,10 +49,9 @@ async fn main() -> Result<()> {
             .parse::<f64>()
             .expect("invalid lon");
 
-        let api_key = std::env::var("TRANSIT_API_KEY").expect("TRANSIT_API_KEY must be set");
-        let stops = TransitClient::new(api_key).resolve_stops(lat, lon).await?;
+        let stops = TransitClient::from_env().resolve_stops(lat, lon).await?;
 
-        println!("{:<32} {:<8} {}", "stop_id", "code", "name");
+        println!("{:<32} {:<8} name", "stop_id", "code");
         println!("{}", "-".repeat(70));
         for stop in &stops {
             println!(
@@ -53,10 +66,336 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    // --board <stop_id> [limit]: next departures at one stop across all routes.
+    if let Some(pos) = args.iter().position(|a| a == "--board") {
+        let stop_id = args.get(pos + 1).expect("--board requires a stop_id");
+        let limit = args
+            .get(pos + 2)
+            .map(|l| l.parse::<usize>().expect("invalid limit"))
+            .unwrap_or(10);
+
+        let board = TransitClient::from_env().next_departures(stop_id, limit).await?;
+
+        println!("{:<6} {:<28} {:>6} {:>14}", "route", "headsign", "min", "delay");
+        println!("{}", "-".repeat(58));
+        for entry in &board {
+            println!(
+                "{:<6} {:<28} {:>6.0} {:>14}",
+                entry.route_short_name,
+                entry.headsign.as_deref().unwrap_or("?"),
+                entry.minutes_away,
+                entry
+                    .delay_seconds
+                    .map(format_delay)
+                    .unwrap_or_else(|| "sched".to_string()),
+            );
+        }
+
+        return Ok(());
+    }
+
+    // --export-csv <file|-> [--route <route>]: dump departure_log as CSV.
+    if let Some(pos) = args.iter().position(|a| a == "--export-csv") {
+        let path = args.get(pos + 1).expect("--export-csv requires a file (or - for stdout)");
+        let route = args
+            .iter()
+            .position(|a| a == "--route")
+            .map(|pos| args.get(pos + 1).expect("--route requires a route").as_str());
+
+        let database_url = std::env::var("DATABASE_URL").context("DATABASE_URL must be set")?;
+        let db = Database::new(&database_url).await?;
+        let written = if path == "-" {
+            db.export_csv(std::io::stdout().lock(), route).await?
+        } else {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path))?;
+            db.export_csv(std::io::BufWriter::new(file), route).await?
+        };
+        eprintln!("Wrote {} rows", written);
+        return Ok(());
+    }
+
+    // --export-geojson <file|-> [--route <route>]: stops with delay stats, for geojson.io/Leaflet.
+    if let Some(pos) = args.iter().position(|a| a == "--export-geojson") {
+        let path = args.get(pos + 1).expect("--export-geojson requires a file (or - for stdout)");
+        let route = args
+            .iter()
+            .position(|a| a == "--route")
+            .map(|pos| args.get(pos + 1).expect("--route requires a route").as_str());
+
+        let database_url = std::env::var("DATABASE_URL").context("DATABASE_URL must be set")?;
+        let geojson = Database::new(&database_url).await?.export_geojson(route).await?;
+        if path == "-" {
+            println!("{}", geojson);
+        } else {
+            std::fs::write(path, geojson).with_context(|| format!("Failed to write {}", path))?;
+        }
+        return Ok(());
+    }
+
+    // --replay <file|dir>: run captured stop_departures JSON through the parser
+    // offline. Directories are replayed in file-name order, so name captures by time.
+    if let Some(pos) = args.iter().position(|a| a == "--replay") {
+        let target = PathBuf::from(args.get(pos + 1).expect("--replay requires a file or directory"));
+        let mut files = if target.is_dir() {
+            std::fs::read_dir(&target)
+                .with_context(|| format!("Failed to list {}", target.display()))?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<std::io::Result<Vec<_>>>()?
+                .into_iter()
+                .filter(|p| p.extension().is_some_and(|e| e == "json"))
+                .collect()
+        } else {
+            vec![target]
+        };
+        files.sort();
+
+        let mut previous: Option<PollResult> = None;
+        for file in &files {
+            // Archived responses carry their fetch time in the name; anything else
+            // falls back to when the file was last written
+            let polled_at = match archive::archived_at(file) {
+                Some(millis) => FeedTime::from_secs(millis / 1000),
+                None => {
+                    let modified = std::fs::metadata(file)
+                        .and_then(|m| m.modified())
+                        .with_context(|| format!("Failed to read the modified time of {}", file.display()))?;
+                    let since_epoch = modified
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .with_context(|| format!("{} was modified before 1970", file.display()))?;
+                    FeedTime::from_secs(since_epoch.as_secs() as i64)
+                }
+            };
+            let poll = PollResult { polled_at, departures: api::parse_file(file)? };
+            let diff = previous.as_ref().map(|p| format!(", {}", poll.diff(p))).unwrap_or_default();
+            println!(
+                "{}: {} departures, {:.0}% real-time{}",
+                file.display(),
+                poll.departures.len(),
+                poll.realtime_coverage() * 100.0,
+                diff
+            );
+            previous = Some(poll);
+        }
+        return Ok(());
+    }
+
+    // --check: one database round-trip and one live fetch, then exit. Non-zero
+    // exit on any failure, so it works as an ExecStartPre or readiness probe.
+    if args.contains(&"--check".to_string()) {
+        let database_url = std::env::var("DATABASE_URL").context("DATABASE_URL must be set")?;
+        let db = Database::new(&database_url).await?;
+        db.ping().await?;
+        let stop_ids = db.get_all_stop_ids().await?;
+        println!("database  ok, {} stops", stop_ids.len());
+        if stop_ids.is_empty() {
+            anyhow::bail!("No stops in the database yet; start the server once to bootstrap them");
+        }
+
+        // One API call, like a single poll chunk
+        let chunk = &stop_ids[..stop_ids.len().min(100)];
+        let poll = PollResult {
+            polled_at: FeedTime::now(),
+            departures: TransitClient::from_env().fetch_stop_departures(chunk).await?,
+        };
+        println!(
+            "api       ok, {} departures at {} stops, {:.0}% real-time",
+            poll.departures.len(),
+            chunk.len(),
+            poll.realtime_coverage() * 100.0
+        );
+
+        let mut trips: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for d in &poll.departures {
+            let route_trips = trips.entry(d.route_short_name.as_str()).or_default();
+            if let Some(trip) = d.rt_trip_id.as_deref() {
+                route_trips.insert(trip);
+            }
+        }
+        for (route, route_trips) in &trips {
+            println!("route {:<4} {} trips", route, route_trips.len());
+        }
+        return Ok(());
+    }
+
+    // --report [--since <unix>] [--until <unix>] [--histogram <secs>]: per-route
+    // summary of what's already in departure_log (default: the last 24 hours),
+    // optionally with each route's delays binned. Reads only the database.
+    if args.contains(&"--report".to_string()) {
+        let arg_secs = |flag: &str| -> Result<Option<i64>> {
+            match args.iter().position(|a| a == flag) {
+                Some(pos) => {
+                    let value = args
+                        .get(pos + 1)
+                        .with_context(|| format!("{} requires a unix time", flag))?;
+                    Ok(Some(value.parse().with_context(|| format!("{} must be unix seconds", flag))?))
+                }
+                None => Ok(None),
+            }
+        };
+        let tz = display_tz()?;
+        let until = arg_secs("--until")?.unwrap_or_else(|| FeedTime::now().secs());
+        let since = arg_secs("--since")?.unwrap_or(until - 86_400);
+        let histogram_bucket: Option<i32> = match args.iter().position(|a| a == "--histogram") {
+            Some(pos) => {
+                let value = args.get(pos + 1).context("--histogram requires a bin width in seconds")?;
+                let secs = value.parse().context("--histogram must be a number of seconds")?;
+                if secs < 1 {
+                    anyhow::bail!("--histogram bin width must be at least 1 second");
+                }
+                Some(secs)
+            }
+            None => None,
+        };
+
+        let database_url = std::env::var("DATABASE_URL").context("DATABASE_URL must be set")?;
+        let db = Database::new(&database_url).await?;
+        let rows = db.departures_between(since, until, None).await?;
+        let now = FeedTime::now();
+        println!(
+            "{} to {}: {} rows",
+            FeedTime::from_secs(since).format_local(&tz, now),
+            FeedTime::from_secs(until).format_local(&tz, now),
+            rows.len()
+        );
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut routes: BTreeMap<(&str, &str), Vec<LoggedDeparture>> = BTreeMap::new();
+        for row in &rows {
+            let d = &row.departure;
+            routes
+                .entry((d.route_short_name.as_str(), d.global_route_id.as_str()))
+                .or_default()
+                .push(row.clone());
+        }
+
+        println!(
+            "{:<6} {:<12} {:>7} {:>6} {:>14} {:>14} {:>8} {:>9} {:>11}",
+            "route", "route_id", "rows", "trips", "avg delay", "max delay", "on time", "bunching", "worst hour"
+        );
+        println!("{}", "-".repeat(95));
+        for ((short_name, route_id), route_rows) in &routes {
+            let departures: Vec<Departure> = route_rows.iter().map(|r| r.departure.clone()).collect();
+            let trips: HashSet<&str> = departures.iter().filter_map(|d| d.rt_trip_id.as_deref()).collect();
+            let stats = average_delay_by_route(&departures).remove(*route_id);
+            let otp = db
+                .on_time_performance(route_id, since, until, DEFAULT_OTP_EARLY_SECS, DEFAULT_OTP_LATE_SECS)
+                .await?;
+            let bunching = bunching_across_polls(route_rows, DEFAULT_BUNCHING_THRESHOLD_SECS).len();
+            let by_hour = db.avg_delay_by_hour(route_id, since, until, tz).await?;
+            let worst_hour = (0..24)
+                .filter_map(|h| by_hour[h].map(|mean| (h, mean)))
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+
+            let na = || "-".to_string();
+            println!(
+                "{:<6} {:<12} {:>7} {:>6} {:>14} {:>14} {:>8} {:>9} {:>11}",
+                short_name,
+                route_id,
+                route_rows.len(),
+                trips.len(),
+                stats.map(|s| format_delay(s.mean.round() as i32)).unwrap_or_else(na),
+                stats.map(|s| format_delay(s.max)).unwrap_or_else(na),
+                otp.fraction().map(|f| format!("{:.0}%", f * 100.0)).unwrap_or_else(na),
+                bunching,
+                worst_hour.map(|(h, _)| format!("{:02}:00", h)).unwrap_or_else(na),
+            );
+        }
+
+        // Every logged prediction counts, so a trip seen by several polls weighs more
+        if let Some(bucket) = histogram_bucket {
+            for ((short_name, route_id), route_rows) in &routes {
+                let departures: Vec<Departure> = route_rows.iter().map(|r| r.departure.clone()).collect();
+                let bins = delay_histogram(&departures, bucket);
+                println!("\nroute {} ({}) delays, {}s bins", short_name, route_id, bucket);
+                if bins.is_empty() {
+                    println!("  no real-time delays");
+                } else {
+                    print!("{}", render_histogram(&bins, bucket, 40));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // --tail [--route <route>]: follow departure_log like `tail -f`.
+    // Useful for watching a running server from another process.
+    if args.contains(&"--tail".to_string()) {
+        let route = args
+            .iter()
+            .position(|a| a == "--route")
+            .map(|pos| args.get(pos + 1).expect("--route requires a route").as_str());
+        let tz = display_tz()?;
+
+        let database_url = std::env::var("DATABASE_URL").context("DATABASE_URL must be set")?;
+        let db = Database::new(&database_url).await?;
+        let mut cursor = db.max_departure_id().await?;
+        // Names from the stops table the server bootstraps; unknown IDs print as-is
+        let stop_names: HashMap<String, String> = db
+            .get_all_stops()
+            .await?
+            .into_iter()
+            .map(|s| (s.global_stop_id, s.stop_name))
+            .collect();
+
+        // Times carry a date once they're not today, e.g. "2025-05-24 08:15:00"
+        println!(
+            "{:<19} {:<6} {:<24} {:<24} {:>19} {:>14}",
+            "polled", "route", "stop", "headsign", "departs", "delay"
+        );
+        println!("{}", "-".repeat(111));
+        loop {
+            let rows = db.departures_after(cursor, route, 500).await?;
+            let now = FeedTime::now();
+            for row in &rows {
+                let d = &row.departure;
+                println!(
+                    "{:<19} {:<6} {:<24} {:<24} {:>19} {:>14}",
+                    row.polled_at.format_local(&tz, now),
+                    d.route_short_name,
+                    truncate(stop_names.get(&d.global_stop_id).unwrap_or(&d.global_stop_id), 24),
+                    d.headsign.as_deref().unwrap_or("?"),
+                    d.departure_time.format_local(&tz, now),
+                    d.delay_seconds
+                        .map(format_delay)
+                        .unwrap_or_else(|| "sched".to_string()),
+                );
+            }
+            match rows.last() {
+                Some(last) => cursor = last.id,
+                None => tokio::time::sleep(Duration::from_secs(2)).await,
+            }
+        }
+    }
+
     eprintln!("Usage:");
     eprintln!("  cargo run -- --discover                    find BBB route IDs near UCLA");
     eprintln!("  cargo run -- --resolve-stops <lat> <lon>   find stop IDs near a coordinate");
+    eprintln!("  cargo run -- --board <stop_id> [limit]      next departures at a stop");
+    eprintln!("  cargo run -- --tail [--route <route>]       follow new departure_log rows");
+    eprintln!("  cargo run -- --check                        check the database and one live fetch");
+    eprintln!("  cargo run -- --report [--since <unix>] [--until <unix>] [--histogram <secs>]");
+    eprintln!("                                              per-route summary from the database");
+    eprintln!("  cargo run -- --export-csv <file|-> [--route <route>]  dump departure_log as CSV");
+    eprintln!("  cargo run -- --export-geojson <file|-> [--route <route>]  stops + delays as GeoJSON");
+    eprintln!("  cargo run -- --replay <file|dir>            parse captured stop_departures JSON offline");
     eprintln!("  cargo run --bin server                      start the collection server");
 
     Ok(())
 }
+
+/// The zone clock times print in, so they match the stop signs. Only parsed
+/// by the commands that print times, so a bad DISPLAY_TZ doesn't break the rest.
+fn display_tz() -> Result<Tz> {
+    match std::env::var("DISPLAY_TZ") {
+        Ok(name) => name.parse().map_err(|e| anyhow::anyhow!("DISPLAY_TZ: {}", e)),
+        Err(_) => Ok(chrono_tz::America::Los_Angeles),
+    }
+}
+
+/// At most `max` characters of `s`, so long stop names keep columns aligned.
+fn truncate(s: &str, max: usize) -> &str {
+    s.char_indices().nth(max).map_or(s, |(i, _)| &s[..i])
+}
@@ -0,0 +1,88 @@
+//! Prometheus metrics, compiled in with `--features metrics` and served at
+//! `GET /metrics` on the API server.
+
+use std::collections::{HashMap, HashSet};
+
+use once_cell::sync::Lazy;
+use prometheus::{Encoder, GaugeVec, IntCounter, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+
+use crate::analysis::average_delay_by_route;
+use crate::models::{FeedTime, PollResult};
+
+struct Metrics {
+    registry: Registry,
+    polls: IntCounter,
+    poll_errors: IntCounter,
+    last_poll: IntGauge,
+    feed_age: IntGauge,
+    active_trips: IntGaugeVec,
+    avg_delay: GaugeVec,
+}
+
+static METRICS: Lazy<Metrics> = Lazy::new(|| {
+    let registry = Registry::new();
+    let polls = IntCounter::new("bbb_poll_total", "Successful polls").unwrap();
+    let poll_errors = IntCounter::new("bbb_poll_errors_total", "Polls that failed to fetch").unwrap();
+    let last_poll = IntGauge::new("bbb_last_poll_timestamp", "Unix time of the last successful poll").unwrap();
+    let feed_age = IntGauge::new("bbb_feed_age_seconds", "Seconds since the last successful poll").unwrap();
+    let active_trips = IntGaugeVec::new(
+        Opts::new("bbb_active_trips", "Distinct trips in the last poll per route"),
+        &["route"],
+    )
+    .unwrap();
+    let avg_delay = GaugeVec::new(
+        Opts::new("bbb_avg_delay_seconds", "Mean real-time delay in the last poll per route"),
+        &["route"],
+    )
+    .unwrap();
+
+    registry.register(Box::new(polls.clone())).unwrap();
+    registry.register(Box::new(poll_errors.clone())).unwrap();
+    registry.register(Box::new(last_poll.clone())).unwrap();
+    registry.register(Box::new(feed_age.clone())).unwrap();
+    registry.register(Box::new(active_trips.clone())).unwrap();
+    registry.register(Box::new(avg_delay.clone())).unwrap();
+
+    Metrics { registry, polls, poll_errors, last_poll, feed_age, active_trips, avg_delay }
+});
+
+pub fn record_poll(result: &PollResult) {
+    let m = &*METRICS;
+    m.polls.inc();
+    m.last_poll.set(result.polled_at.secs());
+
+    // A trip shows up once per stop it serves, so count trip IDs, not departures
+    let mut trips: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for d in &result.departures {
+        let route_trips = trips.entry(&d.global_route_id).or_default();
+        if let Some(trip) = &d.rt_trip_id {
+            route_trips.insert(trip);
+        }
+    }
+    // Reset so routes that dropped out of the poll stop being reported
+    m.active_trips.reset();
+    for (route, route_trips) in trips {
+        m.active_trips.with_label_values(&[route]).set(route_trips.len() as i64);
+    }
+    m.avg_delay.reset();
+    for (route, stats) in average_delay_by_route(&result.departures) {
+        m.avg_delay.with_label_values(&[&route]).set(stats.mean);
+    }
+}
+
+pub fn record_poll_error() {
+    METRICS.poll_errors.inc();
+}
+
+/// Prometheus text exposition of every metric.
+pub fn render() -> String {
+    let m = &*METRICS;
+    if m.last_poll.get() > 0 {
+        m.feed_age.set(FeedTime::now().secs() - m.last_poll.get());
+    }
+    let mut buf = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&m.registry.gather(), &mut buf) {
+        tracing::warn!(error = %e, "Failed to encode metrics");
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
@@ -1,4 +1,97 @@
+use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A Unix timestamp in whole seconds, as the Transit API and departure_log use.
+///
+/// Wraps the raw `i64` so seconds can't be mixed up with milliseconds or
+/// durations. Serializes and binds as a plain integer.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct FeedTime(pub i64);
+
+impl FeedTime {
+    pub fn from_secs(secs: i64) -> Self {
+        Self(secs)
+    }
+
+    pub fn from_millis(millis: i64) -> Self {
+        Self(millis.div_euclid(1000))
+    }
+
+    pub fn now() -> Self {
+        Self(Utc::now().timestamp())
+    }
+
+    pub fn secs(self) -> i64 {
+        self.0
+    }
+
+    /// False for 0 and earlier, which the feed only produces when a time is
+    /// missing; nothing here legitimately happens at or before the epoch.
+    pub fn is_set(self) -> bool {
+        self.0 > 0
+    }
+
+    /// `None` when the value is outside chrono's representable range.
+    pub fn to_datetime<Tz: TimeZone>(self, tz: &Tz) -> Option<DateTime<Tz>> {
+        DateTime::from_timestamp(self.0, 0).map(|dt| dt.with_timezone(tz))
+    }
+
+    /// How long ago this was, relative to `now`. Negative for future times.
+    pub fn age(self, now: FeedTime) -> chrono::Duration {
+        chrono::Duration::seconds(now.0 - self.0)
+    }
+
+    /// Clock time in `tz` as `HH:MM:SS`, "time unknown" when unset (see
+    /// `is_set`) rather than a 1970 date, or "invalid time" when out of range.
+    pub fn format<Tz: TimeZone>(self, tz: &Tz) -> String
+    where
+        Tz::Offset: fmt::Display,
+    {
+        if !self.is_set() {
+            return "time unknown".to_string();
+        }
+        match self.to_datetime(tz) {
+            Some(dt) => dt.format("%H:%M:%S").to_string(),
+            None => "invalid time".to_string(),
+        }
+    }
+
+    /// Like `format`, but prefixed with the date (`YYYY-MM-DD HH:MM:SS`) when
+    /// the time falls on a different local day than `now`.
+    pub fn format_local<Tz: TimeZone>(self, tz: &Tz, now: FeedTime) -> String
+    where
+        Tz::Offset: fmt::Display,
+    {
+        if !self.is_set() {
+            return "time unknown".to_string();
+        }
+        match (self.to_datetime(tz), now.to_datetime(tz)) {
+            (Some(dt), Some(today)) if dt.date_naive() == today.date_naive() => {
+                dt.format("%H:%M:%S").to_string()
+            }
+            (Some(dt), _) => dt.format("%Y-%m-%d %H:%M:%S").to_string(),
+            (None, _) => "invalid time".to_string(),
+        }
+    }
+}
+
+impl From<i64> for FeedTime {
+    fn from(secs: i64) -> Self {
+        Self(secs)
+    }
+}
+
+impl fmt::Display for FeedTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stop {
@@ -8,23 +101,334 @@ pub struct Stop {
     pub lon: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Departure {
     pub global_stop_id: String,
     pub global_route_id: String,
     pub route_short_name: String,
     pub headsign: Option<String>,
-    pub departure_time: i64,
-    pub scheduled_departure_time: i64,
+    pub departure_time: FeedTime,
+    pub scheduled_departure_time: FeedTime,
     pub delay_seconds: Option<i32>,
     pub is_real_time: bool,
     pub is_cancelled: bool,
     pub rt_trip_id: Option<String>,
 }
 
+/// A departure_log row with its identity and poll time.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct LoggedDeparture {
+    pub id: i64,
+    pub polled_at: FeedTime,
+    #[sqlx(flatten)]
+    pub departure: Departure,
+}
+
+/// One row of a departure board: the next departures at a single stop across
+/// every route serving it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoardEntry {
+    pub global_route_id: String,
+    pub route_short_name: String,
+    pub headsign: Option<String>,
+    pub departure_time: FeedTime,
+    pub minutes_away: f64,
+    pub delay_seconds: Option<i32>,
+    pub is_real_time: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PollResult {
-    pub polled_at: i64,
+    pub polled_at: FeedTime,
     pub departures: Vec<Departure>,
 }
 
+/// What changed between two consecutive polls.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PollDiff {
+    pub added: usize,
+    pub removed: usize,
+    pub delays_worsened: usize,
+    pub delays_improved: usize,
+}
+
+impl fmt::Display for PollDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(
+            f,
+            "+{} departure{}, -{} departure{}, {} delay{} worsened, {} improved",
+            self.added,
+            plural(self.added),
+            self.removed,
+            plural(self.removed),
+            self.delays_worsened,
+            plural(self.delays_worsened),
+            self.delays_improved,
+        )
+    }
+}
+
+/// Human-readable delay: "on time", "45s late", "1m 30s early", "1h 1m 1s late".
+/// Leading zero components are dropped.
+pub fn format_delay(seconds: i32) -> String {
+    if seconds == 0 {
+        return "on time".to_string();
+    }
+    let abs = seconds.unsigned_abs();
+    let (h, m, s) = (abs / 3600, abs % 3600 / 60, abs % 60);
+    let amount = if h > 0 {
+        format!("{}h {}m {}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {}s", m, s)
+    } else {
+        format!("{}s", s)
+    };
+    format!("{} {}", amount, if seconds > 0 { "late" } else { "early" })
+}
+
+impl Departure {
+    /// Minutes from `now` until this departure, negative once it has left
+    /// (zero exactly at departure). `None` for cancelled departures, which
+    /// have no prediction.
+    pub fn minutes_until(&self, now: FeedTime) -> Option<f64> {
+        (!self.is_cancelled).then(|| (self.departure_time.secs() - now.secs()) as f64 / 60.0)
+    }
+
+    /// Identifies the same scheduled departure across polls.
+    fn diff_key(&self) -> (&str, &str, FeedTime) {
+        (&self.global_stop_id, &self.global_route_id, self.scheduled_departure_time)
+    }
+}
+
+/// Test departures built up from `sample`, a real-time, on-time route 1
+/// departure toward UCLA at BBB:7023, scheduled at 1000 s with no trip ID.
+#[cfg(test)]
+impl Departure {
+    pub(crate) fn sample() -> Self {
+        Self {
+            global_stop_id: "BBB:7023".to_string(),
+            global_route_id: "BBB:14412".to_string(),
+            route_short_name: "1".to_string(),
+            headsign: Some("UCLA".to_string()),
+            departure_time: FeedTime::from_secs(1_000),
+            scheduled_departure_time: FeedTime::from_secs(1_000),
+            delay_seconds: Some(0),
+            is_real_time: true,
+            is_cancelled: false,
+            rt_trip_id: None,
+        }
+    }
+
+    pub(crate) fn stop(mut self, stop: &str) -> Self {
+        self.global_stop_id = stop.to_string();
+        self
+    }
+
+    pub(crate) fn route(mut self, route_id: &str, short_name: &str) -> Self {
+        self.global_route_id = route_id.to_string();
+        self.route_short_name = short_name.to_string();
+        self
+    }
+
+    pub(crate) fn headsign<'a>(mut self, headsign: impl Into<Option<&'a str>>) -> Self {
+        self.headsign = headsign.into().map(str::to_string);
+        self
+    }
+
+    pub(crate) fn trip<'a>(mut self, trip: impl Into<Option<&'a str>>) -> Self {
+        self.rt_trip_id = trip.into().map(str::to_string);
+        self
+    }
+
+    /// Scheduled at `secs`, keeping the current delay.
+    pub(crate) fn departs(mut self, secs: i64) -> Self {
+        self.scheduled_departure_time = FeedTime::from_secs(secs);
+        let delay = self.delay_seconds;
+        self.delay(delay)
+    }
+
+    /// Predicted `delay` seconds from schedule; `None` is schedule-only.
+    pub(crate) fn delay(mut self, delay: impl Into<Option<i32>>) -> Self {
+        self.delay_seconds = delay.into();
+        self.is_real_time = self.delay_seconds.is_some();
+        let offset = self.delay_seconds.unwrap_or(0) as i64;
+        self.departure_time = FeedTime::from_secs(self.scheduled_departure_time.secs() + offset);
+        self
+    }
+
+    pub(crate) fn cancelled(mut self) -> Self {
+        self.is_cancelled = true;
+        self
+    }
+}
+
+impl PollResult {
+    /// Every route's departures at `stop_id` that haven't left yet as of
+    /// `now`, soonest first. Cancelled departures are left out.
+    pub fn upcoming_at(&self, stop_id: &str, now: FeedTime) -> Vec<&Departure> {
+        let mut upcoming: Vec<_> = self
+            .departures
+            .iter()
+            .filter(|d| d.global_stop_id == stop_id)
+            .filter(|d| d.minutes_until(now).is_some_and(|m| m >= 0.0))
+            .collect();
+        upcoming.sort_by_key(|d| d.departure_time);
+        upcoming
+    }
+
+    /// The soonest departure at `stop_id` that hasn't left yet as of `now`.
+    pub fn next_departure(&self, stop_id: &str, now: FeedTime) -> Option<&Departure> {
+        self.upcoming_at(stop_id, now).into_iter().next()
+    }
+
+    /// Compare against the poll before this one. Departures are matched by
+    /// stop, route and scheduled time; a delay change only counts when both
+    /// polls had a real-time delay.
+    pub fn diff(&self, previous: &PollResult) -> PollDiff {
+        let before: HashMap<_, _> = previous.departures.iter().map(|d| (d.diff_key(), d)).collect();
+        let after: HashMap<_, _> = self.departures.iter().map(|d| (d.diff_key(), d)).collect();
+
+        let mut diff = PollDiff {
+            added: after.keys().filter(|k| !before.contains_key(*k)).count(),
+            removed: before.keys().filter(|k| !after.contains_key(*k)).count(),
+            ..Default::default()
+        };
+        for (key, now) in &after {
+            let Some(prev) = before.get(key) else { continue };
+            match (prev.delay_seconds, now.delay_seconds) {
+                (Some(a), Some(b)) if b > a => diff.delays_worsened += 1,
+                (Some(a), Some(b)) if b < a => diff.delays_improved += 1,
+                _ => {}
+            }
+        }
+        diff
+    }
+
+    /// Fraction of departures backed by a real-time prediction rather than
+    /// only the schedule. A drop flags degraded data. 0.0 for an empty poll.
+    pub fn realtime_coverage(&self) -> f64 {
+        if self.departures.is_empty() {
+            return 0.0;
+        }
+        let realtime = self.departures.iter().filter(|d| d.is_real_time).count();
+        realtime as f64 / self.departures.len() as f64
+    }
+}
+
+/// Tuning for `Database::prediction_accuracy`.
+///
+/// The Transit API doesn't report actual departures, so the last prediction
+/// seen for a trip at a stop stands in for the actual time — but only when that
+/// poll happened close enough to departure to be trusted.
+#[derive(Debug, Clone, Copy)]
+pub struct AccuracyWindow {
+    /// Max seconds between the final poll and the predicted departure for that
+    /// prediction to count as the actual. Tighten for dense stops, loosen when
+    /// polling is sparse. Default: 900 (one poll interval).
+    pub match_window_secs: i64,
+    /// Only score predictions made at most this many seconds before the actual
+    /// departure. Default: 3600.
+    pub horizon_secs: i64,
+}
+
+impl Default for AccuracyWindow {
+    fn default() -> Self {
+        Self { match_window_secs: 900, horizon_secs: 3600 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccuracyStats {
+    pub samples: usize,
+    pub mean_abs_error_secs: Option<f64>,
+    pub max_abs_error_secs: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(departures: Vec<Departure>) -> PollResult {
+        PollResult { polled_at: FeedTime::from_secs(0), departures }
+    }
+
+    #[test]
+    fn realtime_coverage_is_the_real_time_fraction() {
+        let mixed = poll(vec![
+            Departure::sample().departs(600),
+            Departure::sample().departs(1200).delay(None),
+            Departure::sample().stop("BBB:7024").departs(600),
+            Departure::sample().stop("BBB:7024").departs(1200),
+        ]);
+        assert_eq!(mixed.realtime_coverage(), 0.75);
+        assert_eq!(poll(vec![Departure::sample().departs(600).delay(None)]).realtime_coverage(), 0.0);
+        assert_eq!(poll(Vec::new()).realtime_coverage(), 0.0);
+    }
+
+    #[test]
+    fn format_delay_table() {
+        let cases = [
+            (0, "on time"),
+            (45, "45s late"),
+            (59, "59s late"),
+            (60, "1m 0s late"),
+            (90, "1m 30s late"),
+            (3599, "59m 59s late"),
+            (3600, "1h 0m 0s late"),
+            (3661, "1h 1m 1s late"),
+            (-1, "1s early"),
+            (-45, "45s early"),
+            (-60, "1m 0s early"),
+            (-90, "1m 30s early"),
+            (-3600, "1h 0m 0s early"),
+            (-3661, "1h 1m 1s early"),
+        ];
+        for (seconds, expected) in cases {
+            assert_eq!(format_delay(seconds), expected, "format_delay({})", seconds);
+        }
+    }
+
+    #[test]
+    fn departure_at_now_is_still_upcoming() {
+        let now = FeedTime::from_secs(1_000);
+        let at_now = Departure::sample().departs(1_000);
+        let just_left = Departure::sample().departs(999);
+        assert_eq!(at_now.minutes_until(now), Some(0.0));
+        assert_eq!(just_left.minutes_until(now), Some(-1.0 / 60.0));
+        assert_eq!(Departure::sample().departs(1_060).minutes_until(now), Some(1.0));
+
+        let cancelled = Departure::sample().departs(1_030).cancelled();
+        assert_eq!(cancelled.minutes_until(now), None);
+
+        let poll = poll(vec![just_left, cancelled, Departure::sample().departs(1_300), at_now]);
+        let next = poll.next_departure("BBB:7023", now).expect("a departure at now");
+        assert_eq!(next.departure_time, now);
+        let upcoming: Vec<i64> = poll.upcoming_at("BBB:7023", now).iter().map(|d| d.departure_time.secs()).collect();
+        assert_eq!(upcoming, [1_000, 1_300]);
+
+        // One second later it has left
+        let later = FeedTime::from_secs(1_001);
+        assert_eq!(poll.next_departure("BBB:7023", later).map(|d| d.departure_time.secs()), Some(1_300));
+        assert!(poll.next_departure("BBB:7024", now).is_none());
+    }
+
+    #[test]
+    fn format_local_dates_times_off_the_local_day() {
+        let la = chrono_tz::America::Los_Angeles;
+        // 2025-05-24 14:00 Pacific, 21:00 UTC
+        let now = FeedTime::from_secs(1_748_120_400);
+        let at = |offset: i64| FeedTime::from_secs(now.secs() + offset);
+
+        assert_eq!(at(3600).format_local(&la, now), "15:00:00");
+        assert_eq!(at(-52_200).format_local(&la, now), "2025-05-23 23:30:00");
+        assert_eq!(at(36_900).format_local(&la, now), "2025-05-25 00:15:00");
+
+        // 18:30 Pacific is already the 25th in UTC; only the local date counts
+        let evening = at(16_200);
+        assert_eq!(evening.format_local(&la, now), "18:30:00");
+        assert_eq!(evening.format_local(&Utc, now), "2025-05-25 01:30:00");
+
+        assert_eq!(FeedTime::default().format_local(&la, now), "time unknown");
+    }
+}
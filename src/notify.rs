@@ -0,0 +1,214 @@
+//! Push notable conditions to webhooks (Slack, Discord, or anything taking a
+//! JSON POST) instead of only logging them.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::analysis::{trips_exceeding_delay, LateTrip};
+use crate::bunching::{detect_bunching, BunchingEvent};
+use crate::models::{format_delay, Departure, FeedTime};
+
+/// Something worth telling a person about.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MonitorEvent {
+    Bunching(BunchingEvent),
+    /// A trip's worst delay is over the late threshold
+    SevereDelay(LateTrip),
+    /// A trip cancelled at one or more of the polled stops
+    Cancelled {
+        rt_trip_id: String,
+        route_short_name: String,
+        headsign: Option<String>,
+        stops: usize,
+    },
+    /// Polls have been failing for `failing_secs`; `last_poll` is the last
+    /// good one since startup, if any
+    StaleFeed { last_poll: Option<FeedTime>, failing_secs: i64 },
+}
+
+impl MonitorEvent {
+    /// Identifies one ongoing condition across polls, for `EventTracker`.
+    fn key(&self) -> String {
+        match self {
+            MonitorEvent::Bunching(e) => format!(
+                "bunching:{}:{}:{}",
+                e.global_stop_id,
+                e.leading_trip_id.as_deref().unwrap_or("?"),
+                e.trailing_trip_id.as_deref().unwrap_or("?")
+            ),
+            MonitorEvent::SevereDelay(t) => format!("delay:{}", t.rt_trip_id),
+            MonitorEvent::Cancelled { rt_trip_id, .. } => format!("cancelled:{}", rt_trip_id),
+            MonitorEvent::StaleFeed { .. } => "stale".to_string(),
+        }
+    }
+}
+
+fn route_label(route: &str, headsign: Option<&str>) -> String {
+    match headsign {
+        Some(h) => format!("Route {} ({})", route, h),
+        None => format!("Route {}", route),
+    }
+}
+
+impl fmt::Display for MonitorEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonitorEvent::Bunching(e) => write!(
+                f,
+                "{} bunched at {}: trips {} and {} {}s apart",
+                route_label(&e.route_short_name, e.headsign.as_deref()),
+                e.global_stop_id,
+                e.leading_trip_id.as_deref().unwrap_or("?"),
+                e.trailing_trip_id.as_deref().unwrap_or("?"),
+                e.gap_secs
+            ),
+            MonitorEvent::SevereDelay(t) => write!(
+                f,
+                "{} trip {} running {}",
+                route_label(&t.route_short_name, t.headsign.as_deref()),
+                t.rt_trip_id,
+                format_delay(t.max_delay_seconds)
+            ),
+            MonitorEvent::Cancelled { rt_trip_id, route_short_name, headsign, stops } => write!(
+                f,
+                "{} trip {} cancelled at {} stop{}",
+                route_label(route_short_name, headsign.as_deref()),
+                rt_trip_id,
+                stops,
+                if *stops == 1 { "" } else { "s" }
+            ),
+            MonitorEvent::StaleFeed { last_poll: Some(last), failing_secs } => write!(
+                f,
+                "Polls failing for {} min (last success at {})",
+                failing_secs / 60,
+                last
+            ),
+            MonitorEvent::StaleFeed { last_poll: None, failing_secs } => {
+                write!(f, "Polls failing for {} min (none succeeded since startup)", failing_secs / 60)
+            }
+        }
+    }
+}
+
+/// Bunching, severe delays and cancellations in one poll. Trips without an
+/// `rt_trip_id` can't be followed between polls, so only bunching reports them.
+pub fn detect_events(
+    departures: &[Departure],
+    late_threshold_secs: i32,
+    bunching_threshold_secs: i64,
+) -> Vec<MonitorEvent> {
+    let mut events: Vec<MonitorEvent> = detect_bunching(departures, bunching_threshold_secs)
+        .into_iter()
+        .map(MonitorEvent::Bunching)
+        .collect();
+    events.extend(
+        trips_exceeding_delay(departures, late_threshold_secs)
+            .into_iter()
+            .map(MonitorEvent::SevereDelay),
+    );
+
+    let mut cancelled: BTreeMap<&str, (&Departure, usize)> = BTreeMap::new();
+    for d in departures.iter().filter(|d| d.is_cancelled) {
+        if let Some(trip) = d.rt_trip_id.as_deref() {
+            cancelled.entry(trip).or_insert((d, 0)).1 += 1;
+        }
+    }
+    events.extend(cancelled.into_iter().map(|(trip, (d, stops))| MonitorEvent::Cancelled {
+        rt_trip_id: trip.to_string(),
+        route_short_name: d.route_short_name.clone(),
+        headsign: d.headsign.clone(),
+        stops,
+    }));
+    events
+}
+
+/// Passes on only the events that weren't active at the previous check, so
+/// an ongoing condition notifies once when it starts rather than every poll.
+/// A condition that clears and later returns notifies again.
+#[derive(Debug, Default)]
+pub struct EventTracker {
+    active: HashSet<String>,
+}
+
+impl EventTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `events` as the full set active now and return the new ones.
+    pub fn transitions(&mut self, events: Vec<MonitorEvent>) -> Vec<MonitorEvent> {
+        let now: HashSet<String> = events.iter().map(MonitorEvent::key).collect();
+        let fresh = events.into_iter().filter(|e| !self.active.contains(&e.key())).collect();
+        self.active = now;
+        fresh
+    }
+}
+
+/// Somewhere events get delivered.
+pub trait Notifier {
+    fn notify(&self, event: &MonitorEvent) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// POSTs each event as JSON:
+///
+/// ```json
+/// { "text": "Route 1 (UCLA) trip 1001 running 17m 0s late",
+///   "content": "Route 1 (UCLA) trip 1001 running 17m 0s late",
+///   "event": { "kind": "severe_delay", "rt_trip_id": "1001", ... } }
+/// ```
+///
+/// `text` is what Slack incoming webhooks show and `content` what Discord
+/// shows; anything else can read `event`.
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    text: &'a str,
+    content: &'a str,
+    event: &'a MonitorEvent,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        match reqwest::Url::parse(&url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+            // Only the scheme is echoed back; the rest of a webhook URL is a secret
+            Ok(parsed) => anyhow::bail!("Webhook URL must be http or https, got {}://", parsed.scheme()),
+            Err(e) => anyhow::bail!("Invalid webhook URL: {}", e),
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .context("Failed to create HTTP client")?;
+        Ok(Self::from_client(client, url))
+    }
+
+    pub fn from_client(client: reqwest::Client, url: impl Into<String>) -> Self {
+        Self { client, url: url.into() }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, event: &MonitorEvent) -> Result<()> {
+        let text = event.to_string();
+        self.client
+            .post(&self.url)
+            .json(&WebhookPayload { text: &text, content: &text, event })
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            // Webhook URLs embed their secret; keep it out of logs
+            .map_err(|e| anyhow::anyhow!("Webhook request failed: {}", e.without_url()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::models::PollResult;
+
+/// File header; bump the last byte whenever `PollResult`'s layout changes so
+/// caches written by an older build are ignored instead of misread.
+const MAGIC: &[u8; 8] = b"BBBSNAP\x01";
+
+/// Write the latest poll to `path` (via a temp file, so a crash mid-write
+/// can't leave a truncated cache behind).
+pub fn save(path: &Path, poll: &PollResult) -> Result<()> {
+    let mut buf = MAGIC.to_vec();
+    bincode::serialize_into(&mut buf, poll).context("Failed to encode snapshot")?;
+
+    // Beside the cache with ".tmp" appended, never clashing with `path` itself
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, &buf).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Load a snapshot written by `save`. Returns `None` when the file is missing
+/// or was written by an incompatible version.
+pub fn load(path: &Path) -> Result<Option<PollResult>> {
+    let bytes = match std::fs::read(path) {
+        Ok(b) => b,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let Some(body) = bytes.strip_prefix(MAGIC.as_slice()) else {
+        tracing::warn!(path = %path.display(), "Ignoring snapshot cache with unknown header");
+        return Ok(None);
+    };
+
+    match bincode::deserialize(body) {
+        Ok(poll) => Ok(Some(poll)),
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "Ignoring unreadable snapshot cache");
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Departure, FeedTime};
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("bbb-snapshot-{:016x}", rand::random::<u64>()));
+            std::fs::create_dir(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn poll() -> PollResult {
+        let departure = Departure::sample().trip("1001").departs(1_748_120_400).delay(60);
+        PollResult { polled_at: FeedTime::from_secs(1_748_119_500), departures: vec![departure] }
+    }
+
+    #[test]
+    fn saved_poll_loads_back() {
+        let dir = TempDir::new();
+        let path = dir.0.join("snapshot.bin");
+        save(&path, &poll()).unwrap();
+
+        let loaded = load(&path).unwrap().expect("snapshot present");
+        assert_eq!(loaded.polled_at, poll().polled_at);
+        assert_eq!(loaded.departures.len(), 1);
+        let (got, want) = (&loaded.departures[0], &poll().departures[0]);
+        assert_eq!(got.rt_trip_id, want.rt_trip_id);
+        assert_eq!(got.headsign, want.headsign);
+        assert_eq!(got.departure_time, want.departure_time);
+        assert_eq!(got.delay_seconds, want.delay_seconds);
+        // Only the cache itself is left behind
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn missing_file_is_no_snapshot() {
+        let dir = TempDir::new();
+        assert!(load(&dir.0.join("snapshot.bin")).unwrap().is_none());
+    }
+
+    #[test]
+    fn unknown_header_is_ignored() {
+        let dir = TempDir::new();
+        let path = dir.0.join("snapshot.bin");
+        save(&path, &poll()).unwrap();
+        // As if written by a build with a different PollResult layout
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[7] = 0;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(load(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_body_is_ignored() {
+        let dir = TempDir::new();
+        let path = dir.0.join("snapshot.bin");
+        save(&path, &poll()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        assert!(load(&path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use futures::{Stream, StreamExt};
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+use crate::api::{parse_stop_departures, TransitClient};
+use crate::filter::RouteMatcher;
+use crate::models::Departure;
+
+/// Anything that can answer "upcoming departures at these stops".
+///
+/// `TransitClient` is the live implementation; the file and in-memory
+/// sources replay captured stop_departures responses through the same
+/// `parse_stop_departures` path, so polling can run without a network.
+pub trait DepartureSource {
+    fn fetch_departures(&self, stop_ids: &[&str]) -> impl Future<Output = Result<Vec<Departure>>> + Send;
+}
+
+impl DepartureSource for TransitClient {
+    fn fetch_departures(&self, stop_ids: &[&str]) -> impl Future<Output = Result<Vec<Departure>>> + Send {
+        self.fetch_stop_departures(stop_ids)
+    }
+}
+
+/// Keep only the requested stops, as the live API would.
+fn only_stops(departures: Vec<Departure>, stop_ids: &[&str]) -> Vec<Departure> {
+    departures
+        .into_iter()
+        .filter(|d| stop_ids.contains(&d.global_stop_id.as_str()))
+        .collect()
+}
+
+/// A captured stop_departures JSON body held in memory.
+#[derive(Debug, Clone)]
+pub struct StaticDepartureSource {
+    body: Vec<u8>,
+}
+
+impl StaticDepartureSource {
+    pub fn new(body: impl Into<Vec<u8>>) -> Self {
+        Self { body: body.into() }
+    }
+}
+
+impl DepartureSource for StaticDepartureSource {
+    async fn fetch_departures(&self, stop_ids: &[&str]) -> Result<Vec<Departure>> {
+        Ok(only_stops(parse_stop_departures(&self.body)?, stop_ids))
+    }
+}
+
+/// A captured stop_departures JSON file, re-read on every fetch so it can be
+/// swapped out while a poll loop runs.
+#[derive(Debug, Clone)]
+pub struct FileDepartureSource {
+    path: PathBuf,
+}
+
+impl FileDepartureSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl DepartureSource for FileDepartureSource {
+    async fn fetch_departures(&self, stop_ids: &[&str]) -> Result<Vec<Departure>> {
+        let body = tokio::fs::read(&self.path)
+            .await
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        let departures = parse_stop_departures(&body)
+            .with_context(|| format!("Failed to decode {}", self.path.display()))?;
+        Ok(only_stops(departures, stop_ids))
+    }
+}
+
+/// All departures for `stop_ids`, fetched in API-sized chunks of 100.
+async fn fetch_all<C: DepartureSource>(source: &C, stop_ids: &[String]) -> Result<Vec<Departure>> {
+    let mut all = Vec::new();
+    for chunk in stop_ids.chunks(100) {
+        let chunk: Vec<&str> = chunk.iter().map(String::as_str).collect();
+        all.extend(source.fetch_departures(&chunk).await?);
+    }
+    Ok(all)
+}
+
+/// Fetch `stop_ids` from `source` now and then every `interval`, yielding
+/// each batch. A failed tick yields an `Err` and the stream keeps going, so
+/// one outage doesn't end a long-running consumer.
+///
+/// Unlike the server's poll loop this doesn't space chunks 13 s apart; keep
+/// `stop_ids` to one chunk or the interval generous on the free API tier.
+pub fn departure_stream<C: DepartureSource>(
+    source: &C,
+    stop_ids: Vec<String>,
+    interval: Duration,
+) -> impl Stream<Item = Result<Vec<Departure>>> + '_ {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    futures::stream::unfold((ticker, stop_ids), move |(mut ticker, stop_ids)| async move {
+        ticker.tick().await;
+        let batch = fetch_all(source, &stop_ids).await;
+        Some((batch, (ticker, stop_ids)))
+    })
+}
+
+/// `departure_stream`, keeping only departures on routes `matcher` accepts.
+pub fn departure_stream_matching<C: DepartureSource>(
+    source: &C,
+    stop_ids: Vec<String>,
+    interval: Duration,
+    matcher: RouteMatcher,
+) -> impl Stream<Item = Result<Vec<Departure>>> + '_ {
+    departure_stream(source, stop_ids, interval).map(move |batch| {
+        batch.map(|deps| deps.into_iter().filter(|d| matcher.matches_departure(d)).collect())
+    })
+}
@@ -0,0 +1,162 @@
+//! JSON contract for the HTTP API.
+//!
+//! Every field carries an explicit `#[serde(rename)]` so renaming something in
+//! `models.rs` can't silently change what the map and downstream apps receive.
+
+use serde::Serialize;
+
+use crate::models::{Departure, PollResult, Stop};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WireStop {
+    #[serde(rename = "global_stop_id")]
+    pub global_stop_id: String,
+    #[serde(rename = "stop_name")]
+    pub stop_name: String,
+    #[serde(rename = "lat")]
+    pub lat: f64,
+    #[serde(rename = "lon")]
+    pub lon: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WireDeparture {
+    #[serde(rename = "global_stop_id")]
+    pub global_stop_id: String,
+    #[serde(rename = "global_route_id")]
+    pub global_route_id: String,
+    #[serde(rename = "route_short_name")]
+    pub route_short_name: String,
+    #[serde(rename = "headsign")]
+    pub headsign: Option<String>,
+    #[serde(rename = "departure_time")]
+    pub departure_time: i64,
+    #[serde(rename = "scheduled_departure_time")]
+    pub scheduled_departure_time: i64,
+    #[serde(rename = "delay_seconds")]
+    pub delay_seconds: Option<i32>,
+    #[serde(rename = "is_real_time")]
+    pub is_real_time: bool,
+    #[serde(rename = "is_cancelled")]
+    pub is_cancelled: bool,
+    #[serde(rename = "rt_trip_id")]
+    pub rt_trip_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WirePollResult {
+    #[serde(rename = "polled_at")]
+    pub polled_at: i64,
+    #[serde(rename = "departures")]
+    pub departures: Vec<WireDeparture>,
+}
+
+/// Everything the map needs in one document: stops plus the latest poll.
+#[derive(Debug, Clone, Serialize)]
+pub struct WireSnapshot {
+    #[serde(rename = "generated_at")]
+    pub generated_at: i64,
+    #[serde(rename = "stale")]
+    pub stale: bool,
+    #[serde(rename = "stops")]
+    pub stops: Vec<WireStop>,
+    #[serde(rename = "poll")]
+    pub poll: Option<WirePollResult>,
+}
+
+impl From<&Stop> for WireStop {
+    fn from(s: &Stop) -> Self {
+        Self {
+            global_stop_id: s.global_stop_id.clone(),
+            stop_name: s.stop_name.clone(),
+            lat: s.lat,
+            lon: s.lon,
+        }
+    }
+}
+
+impl From<&Departure> for WireDeparture {
+    fn from(d: &Departure) -> Self {
+        Self {
+            global_stop_id: d.global_stop_id.clone(),
+            global_route_id: d.global_route_id.clone(),
+            route_short_name: d.route_short_name.clone(),
+            headsign: d.headsign.clone(),
+            departure_time: d.departure_time.secs(),
+            scheduled_departure_time: d.scheduled_departure_time.secs(),
+            delay_seconds: d.delay_seconds,
+            is_real_time: d.is_real_time,
+            is_cancelled: d.is_cancelled,
+            rt_trip_id: d.rt_trip_id.clone(),
+        }
+    }
+}
+
+impl From<&PollResult> for WirePollResult {
+    fn from(p: &PollResult) -> Self {
+        Self {
+            polled_at: p.polled_at.secs(),
+            departures: p.departures.iter().map(WireDeparture::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FeedTime;
+
+    fn keys<T: Serialize>(value: &T) -> Vec<String> {
+        let value = serde_json::to_value(value).unwrap();
+        let object = value.as_object().expect("serializes as an object");
+        let mut keys: Vec<String> = object.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn departure_field_names() {
+        assert_eq!(
+            keys(&WireDeparture::from(&Departure::sample())),
+            [
+                "delay_seconds",
+                "departure_time",
+                "global_route_id",
+                "global_stop_id",
+                "headsign",
+                "is_cancelled",
+                "is_real_time",
+                "route_short_name",
+                "rt_trip_id",
+                "scheduled_departure_time",
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_field_names() {
+        let stop = Stop {
+            global_stop_id: "BBB:7023".into(),
+            stop_name: "Westwood".into(),
+            lat: 34.06,
+            lon: -118.44,
+        };
+        let departure = Departure::sample().trip("1001").departs(1748122680).delay(1020);
+        let poll = PollResult { polled_at: FeedTime::from_secs(1748123000), departures: vec![departure] };
+        let snapshot = WireSnapshot {
+            generated_at: 1748123010,
+            stale: false,
+            stops: vec![WireStop::from(&stop)],
+            poll: Some(WirePollResult::from(&poll)),
+        };
+
+        assert_eq!(keys(&snapshot.stops[0]), ["global_stop_id", "lat", "lon", "stop_name"]);
+        assert_eq!(keys(snapshot.poll.as_ref().unwrap()), ["departures", "polled_at"]);
+        assert_eq!(keys(&snapshot), ["generated_at", "poll", "stale", "stops"]);
+
+        // Times go out as plain Unix seconds
+        let value = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(value["poll"]["polled_at"], 1748123000);
+        assert_eq!(value["poll"]["departures"][0]["departure_time"], 1748123700);
+    }
+}
@@ -0,0 +1,140 @@
+//! `TransitClient` over real HTTP, against a local stand-in for the Transit
+//! API or a host that doesn't exist.
+
+use std::io::Write;
+
+use axum::body::Body;
+use axum::http::{header, Response};
+use axum::routing::get;
+use axum::Router;
+use bigbluebunch::api::{DnsResolutionError, RetryPolicy};
+use bigbluebunch::{parse_stop_departures, FeedTime, TransitClient};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::json;
+
+const WESTWOOD: &[u8] = include_bytes!("fixtures/stop_departures_westwood.json");
+
+/// Serve `body` as every stop_departures response on a local port, labelled
+/// with `encoding` if given, and return a client pointed at it.
+async fn serve_stop_departures(body: Vec<u8>, encoding: Option<&'static str>) -> TransitClient {
+    let handler = move || async move {
+        let mut response = Response::builder().header(header::CONTENT_TYPE, "application/json");
+        if let Some(encoding) = encoding {
+            response = response.header(header::CONTENT_ENCODING, encoding);
+        }
+        response.body(Body::from(body)).unwrap()
+    };
+    let app = Router::new().route("/v4/public/stop_departures", get(handler));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    TransitClient::builder("key")
+        .base_url(base_url)
+        .retry_policy(RetryPolicy::none())
+        .build()
+        .unwrap()
+}
+
+/// A real-time schedule item departing `offset` seconds from `now`.
+fn item(now: i64, offset: i64, trip: &str, cancelled: bool) -> serde_json::Value {
+    json!({
+        "departure_time": now + offset,
+        "scheduled_departure_time": now + offset,
+        "is_real_time": true,
+        "is_cancelled": cancelled,
+        "rt_trip_id": trip,
+    })
+}
+
+fn route_at(stop: &str, route_id: &str, short_name: &str, items: Vec<serde_json::Value>) -> serde_json::Value {
+    json!({
+        "global_route_id": route_id,
+        "route_short_name": short_name,
+        "global_stop_id": stop,
+        "merged_itineraries": [{ "itineraries": [{ "headsign": "UCLA" }], "schedule_items": items }],
+    })
+}
+
+#[tokio::test]
+async fn next_departures_across_routes() {
+    let now = FeedTime::now().secs();
+    let body = json!({
+        "route_departures": [
+            route_at("BBB:7023", "BBB:14412", "1", vec![
+                item(now, -120, "1001", false),
+                item(now, 600, "1002", false),
+                item(now, 1500, "1003", false),
+            ]),
+            route_at("BBB:7023", "BBB:14430", "R12", vec![
+                item(now, 300, "1201", false),
+                item(now, 900, "1202", true),
+                item(now, 1200, "1203", false),
+            ]),
+            // Another stop the response happens to include
+            route_at("BBB:7041", "BBB:14412", "1", vec![item(now, 60, "1004", false)]),
+        ]
+    });
+    let client = serve_stop_departures(body.to_string().into_bytes(), None).await;
+
+    // Soonest first across both routes; the one that's left and the cancelled one are skipped
+    let board = client.next_departures("BBB:7023", 3).await.unwrap();
+    let seen: Vec<(&str, i64)> = board
+        .iter()
+        .map(|e| (e.route_short_name.as_str(), e.departure_time.secs() - now))
+        .collect();
+    assert_eq!(seen, [("R12", 300), ("1", 600), ("R12", 1200)]);
+    // A test running slow can only shrink the wait
+    assert!((4.9..=5.0).contains(&board[0].minutes_away), "{}", board[0].minutes_away);
+
+    let everything = client.next_departures("BBB:7023", 10).await.unwrap();
+    assert_eq!(everything.len(), 4);
+    assert!(client.next_departures("BBB:7023", 0).await.unwrap().is_empty());
+    assert!(client.next_departures("BBB:9999", 10).await.unwrap().is_empty());
+}
+
+fn gzip(body: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[tokio::test]
+async fn gzip_response_is_decoded() {
+    let client = serve_stop_departures(gzip(WESTWOOD), Some("gzip")).await;
+    let fetched = client.fetch_stop_departures(&["BBB:7023", "BBB:7041"]).await.unwrap();
+
+    let expected = parse_stop_departures(WESTWOOD).unwrap();
+    assert_eq!(fetched.len(), expected.len());
+    for (got, want) in fetched.iter().zip(&expected) {
+        assert_eq!(got.rt_trip_id, want.rt_trip_id);
+        assert_eq!(got.departure_time, want.departure_time);
+    }
+}
+
+#[tokio::test]
+async fn unlabelled_gzip_is_reported_as_compressed() {
+    // As if a proxy stripped Content-Encoding on the way through
+    let client = serve_stop_departures(gzip(WESTWOOD), None).await;
+    let err = client.fetch_stop_departures(&["BBB:7023"]).await.unwrap_err();
+    assert!(format!("{:#}", err).contains("still gzip-compressed"), "{:#}", err);
+}
+
+#[tokio::test]
+async fn unresolvable_host_is_a_dns_error() {
+    // .invalid is reserved and never resolves (RFC 6761)
+    let client = TransitClient::builder("key")
+        .base_url("http://nonexistent.invalid")
+        .retry_policy(RetryPolicy::none())
+        .build()
+        .unwrap();
+
+    let err = client.fetch_stop_departures(&["BBB:7023"]).await.unwrap_err();
+    let dns = err
+        .chain()
+        .find_map(|e| e.downcast_ref::<DnsResolutionError>())
+        .unwrap_or_else(|| panic!("not a DNS error: {:#}", err));
+    assert_eq!(dns.host, "nonexistent.invalid");
+    assert!(format!("{:#}", err).contains("could not resolve nonexistent.invalid"));
+}
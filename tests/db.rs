@@ -0,0 +1,420 @@
+//! `Database` queries against a real Postgres.
+//!
+//! Each test creates its own migrated database on the server behind
+//! `DATABASE_URL` and drops it when it passes, so nothing touches the data
+//! already there. Without `DATABASE_URL` the tests skip (and pass), keeping
+//! `cargo test` runnable with no database.
+
+use std::ops::Deref;
+use std::time::Duration;
+
+use bigbluebunch::models::{AccuracyWindow, Stop};
+use bigbluebunch::{BufferedWriter, Database, Departure, FeedTime};
+use sqlx::postgres::PgConnectOptions;
+use sqlx::PgPool;
+
+const ROUTE: &str = "BBB:14412";
+/// 2025-05-24 14:00 Pacific
+const DAY_1: i64 = 1748120400;
+const DAY_2: i64 = DAY_1 + 86_400;
+
+struct TestDb {
+    db: Database,
+    admin: PgPool,
+    name: String,
+    options: PgConnectOptions,
+}
+
+impl Deref for TestDb {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.db
+    }
+}
+
+impl TestDb {
+    /// Names of the indexes on `table`, sorted.
+    async fn indexes(&self, table: &str) -> Vec<String> {
+        let pool = PgPool::connect_with(self.options.clone()).await.expect("connect to test database");
+        let query = "SELECT indexname FROM pg_indexes WHERE tablename = $1 ORDER BY indexname";
+        let names = sqlx::query_scalar(query)
+            .bind(table)
+            .fetch_all(&pool)
+            .await
+            .expect("list indexes");
+        pool.close().await;
+        names
+    }
+
+    async fn drop_database(self) {
+        self.db.close().await;
+        sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", self.name))
+            .execute(&self.admin)
+            .await
+            .expect("drop test database");
+    }
+}
+
+async fn test_db() -> Option<TestDb> {
+    let Ok(url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL not set, skipping");
+        return None;
+    };
+    let admin = PgPool::connect(&url).await.expect("connect to DATABASE_URL");
+    let name = format!("bbb_test_{:016x}", rand::random::<u64>());
+    sqlx::query(&format!("CREATE DATABASE {}", name))
+        .execute(&admin)
+        .await
+        .expect("create test database");
+
+    let options: PgConnectOptions = url.parse().expect("valid DATABASE_URL");
+    let options = options.database(&name);
+    let db = Database::connect_with(options.clone()).await.expect("migrate test database");
+    Some(TestDb { db, admin, name, options })
+}
+
+/// A route 1 departure at `stop`. `delay` makes it real-time, predicted that
+/// far from `scheduled`; `None` makes it schedule-only.
+fn departure(stop: &str, trip: &str, scheduled: i64, delay: Option<i32>) -> Departure {
+    Departure {
+        global_stop_id: stop.to_string(),
+        global_route_id: ROUTE.to_string(),
+        route_short_name: "1".to_string(),
+        headsign: Some("UCLA".to_string()),
+        departure_time: FeedTime::from_secs(scheduled + delay.unwrap_or(0) as i64),
+        scheduled_departure_time: FeedTime::from_secs(scheduled),
+        delay_seconds: delay,
+        is_real_time: delay.is_some(),
+        is_cancelled: false,
+        rt_trip_id: Some(trip.to_string()),
+    }
+}
+
+async fn log(db: &Database, polled_at: i64, departures: &[Departure]) {
+    db.insert_departure_log(FeedTime::from_secs(polled_at), departures)
+        .await
+        .expect("insert departures");
+}
+
+#[tokio::test]
+async fn prediction_accuracy_scores_each_day_separately() {
+    let Some(db) = test_db().await else { return };
+
+    // Trip 1001 runs on both days. Day 1: predicted 300 s late, finally 120 s
+    log(&db, DAY_1 - 1800, &[departure("BBB:7023", "1001", DAY_1, Some(300))]).await;
+    log(&db, DAY_1 - 300, &[departure("BBB:7023", "1001", DAY_1, Some(120))]).await;
+    // Day 2: predicted 600 s late, finally on time
+    log(&db, DAY_2 - 1200, &[departure("BBB:7023", "1001", DAY_2, Some(600))]).await;
+    log(&db, DAY_2 - 200, &[departure("BBB:7023", "1001", DAY_2, Some(0))]).await;
+
+    // None of these are predictions to score: schedule-only, another route,
+    // and a poll before the range
+    log(&db, DAY_2 - 600, &[departure("BBB:7023", "1001", DAY_2, None)]).await;
+    let mut other_route = departure("BBB:7023", "1001", DAY_2, Some(5000));
+    other_route.global_route_id = "BBB:14430".to_string();
+    log(&db, DAY_2 - 900, &[other_route]).await;
+    log(&db, DAY_1 - 3000, &[departure("BBB:7023", "1001", DAY_1, Some(2000))]).await;
+
+    let stats = db
+        .prediction_accuracy(ROUTE, DAY_1 - 1800, DAY_2, &AccuracyWindow::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.samples, 2);
+    assert_eq!(stats.mean_abs_error_secs, Some(390.0));
+    assert_eq!(stats.max_abs_error_secs, Some(600));
+
+    db.drop_database().await;
+}
+
+fn stop(id: &str) -> Stop {
+    Stop { global_stop_id: id.to_string(), stop_name: id.to_string(), lat: 34.0, lon: -118.4 }
+}
+
+#[tokio::test]
+async fn route_stops_follow_the_routes_asked_for() {
+    let Some(db) = test_db().await else { return };
+
+    // BBB:7023 is shared by both routes
+    db.upsert_route_stops(ROUTE, &[stop("BBB:7023"), stop("BBB:7024")]).await.unwrap();
+    db.upsert_route_stops("BBB:14430", &[stop("BBB:7023"), stop("BBB:9000")]).await.unwrap();
+    // Bootstrapping again is harmless
+    db.upsert_route_stops(ROUTE, &[stop("BBB:7023")]).await.unwrap();
+
+    let routes = db.bootstrapped_routes().await.unwrap();
+    assert_eq!(routes.len(), 2);
+    assert!(routes.contains(ROUTE) && routes.contains("BBB:14430"));
+
+    let route_1 = db.stop_ids_for_routes(&[ROUTE.to_string()]).await.unwrap();
+    assert_eq!(route_1, ["BBB:7023", "BBB:7024"]);
+    let both = db.stop_ids_for_routes(&[ROUTE.to_string(), "BBB:14430".to_string()]).await.unwrap();
+    assert_eq!(both, ["BBB:7023", "BBB:7024", "BBB:9000"]);
+    assert!(db.stop_ids_for_routes(&["BBB:99999".to_string()]).await.unwrap().is_empty());
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn buffered_writer_holds_rows_until_a_threshold() {
+    let Some(db) = test_db().await else { return };
+    let logged = || db.count_departures_by_route(ROUTE);
+
+    let mut writer = BufferedWriter::new(&db, 3, Duration::from_secs(3600));
+    let poll = [departure("BBB:7023", "1001", DAY_1, Some(60)), departure("BBB:7024", "1001", DAY_1, Some(60))];
+    assert_eq!(writer.push(FeedTime::from_secs(DAY_1 - 900), &poll).await.unwrap(), 0);
+    assert_eq!(writer.len(), 2);
+    assert_eq!(logged().await.unwrap(), 0);
+
+    // The third row reaches max_rows and writes all of them
+    let next = [departure("BBB:7023", "1002", DAY_1 + 900, Some(0))];
+    assert_eq!(writer.push(FeedTime::from_secs(DAY_1), &next).await.unwrap(), 3);
+    assert!(writer.is_empty());
+    assert_eq!(logged().await.unwrap(), 3);
+
+    // What's left at shutdown goes out with flush; a repeat of a logged row is skipped
+    writer.push(FeedTime::from_secs(DAY_1), &next).await.unwrap();
+    writer.push(FeedTime::from_secs(DAY_1 + 900), &next).await.unwrap();
+    assert_eq!(writer.flush().await.unwrap(), 1);
+    assert_eq!(writer.flush().await.unwrap(), 0);
+    assert_eq!(logged().await.unwrap(), 4);
+
+    // With no age allowed, every push writes through
+    let mut unbuffered = BufferedWriter::new(&db, 10_000, Duration::ZERO);
+    let later = [departure("BBB:7023", "1003", DAY_1 + 1800, None)];
+    assert_eq!(unbuffered.push(FeedTime::from_secs(DAY_1 + 900), &later).await.unwrap(), 1);
+    assert_eq!(logged().await.unwrap(), 5);
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn buffered_writer_keeps_only_unwritten_rows_after_a_failure() {
+    let Some(db) = test_db().await else { return };
+    let logged = || db.count_departures_by_route(ROUTE);
+    let polled_at = FeedTime::from_secs(DAY_1 - 900);
+
+    // Postgres rejects NUL in text, so the second chunk fails after the first commits
+    let mut writer = BufferedWriter::new(&db, 2, Duration::from_secs(3600))
+        .with_chunk_size(2)
+        .with_max_buffered(2);
+    let poll = [
+        departure("BBB:7023", "1001", DAY_1, Some(60)),
+        departure("BBB:7023", "1002", DAY_1 + 900, Some(60)),
+        departure("BBB:7023", "bad\0", DAY_1 + 1800, Some(60)),
+        departure("BBB:7023", "1003", DAY_1 + 2700, Some(60)),
+    ];
+    let err = writer.push(polled_at, &poll).await.unwrap_err();
+    assert_eq!((err.committed, err.inserted), (2, 2));
+    assert_eq!(writer.len(), 2);
+    assert_eq!(logged().await.unwrap(), 2);
+
+    // Still failing, so past the cap the oldest row goes: here the bad one
+    let next = [departure("BBB:7023", "1004", DAY_1 + 3600, Some(60))];
+    let err = writer.push(polled_at, &next).await.unwrap_err();
+    assert_eq!((err.committed, err.inserted), (0, 0));
+    assert_eq!(writer.len(), 2);
+
+    assert_eq!(writer.flush().await.unwrap(), 2);
+    assert!(writer.is_empty());
+    assert_eq!(logged().await.unwrap(), 4);
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn logging_the_same_departure_twice_keeps_one_row() {
+    let Some(db) = test_db().await else { return };
+
+    let poll = [departure("BBB:7023", "1001", DAY_1, Some(60))];
+    let polled_at = FeedTime::from_secs(DAY_1 - 900);
+    assert_eq!(db.insert_departure_log(polled_at, &poll).await.unwrap(), 1);
+    assert_eq!(db.insert_departure_log(polled_at, &poll).await.unwrap(), 0);
+    assert_eq!(db.count_departures_by_route(ROUTE).await.unwrap(), 1);
+
+    // The same departure seen by a later poll is a new observation
+    let later = FeedTime::from_secs(DAY_1 - 300);
+    assert_eq!(db.insert_departure_log(later, &poll).await.unwrap(), 1);
+    assert_eq!(db.count_departures_by_route(ROUTE).await.unwrap(), 2);
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn on_time_performance_judges_each_day_separately() {
+    let Some(db) = test_db().await else { return };
+
+    // Trip 1001 on day 1: 600 s late early on, finally 30 s late (on time)
+    log(&db, DAY_1 - 1800, &[departure("BBB:7023", "1001", DAY_1, Some(600))]).await;
+    log(&db, DAY_1 - 300, &[departure("BBB:7023", "1001", DAY_1, Some(30))]).await;
+    // The same trip on day 2 finishes 400 s late, which mustn't hide day 1
+    log(&db, DAY_2 - 300, &[departure("BBB:7023", "1001", DAY_2, Some(400))]).await;
+
+    let otp = db.on_time_performance(ROUTE, DAY_1 - 3600, DAY_2, 60, 300).await.unwrap();
+    assert_eq!(otp.samples, 2);
+    assert_eq!(otp.on_time, 1);
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn delay_percentiles_over_known_delays() {
+    let Some(db) = test_db().await else { return };
+
+    // Absolute delays 1..=100 s, half of them early
+    let poll: Vec<Departure> = (1..=100)
+        .map(|i| {
+            let delay = if i % 2 == 0 { i } else { -i };
+            departure(&format!("BBB:{}", 7000 + i), "1001", DAY_1, Some(delay))
+        })
+        .collect();
+    log(&db, DAY_1 - 600, &poll).await;
+    // Schedule-only rows carry no delay to rank
+    log(&db, DAY_1 - 300, &[departure("BBB:7001", "1001", DAY_1, None)]).await;
+
+    let percentiles = db.delay_percentiles(ROUTE, DAY_1 - 3600, DAY_1, &[0.5, 0.9, 0.99]).await.unwrap();
+    let expected = [(0.5, 50.5), (0.9, 90.1), (0.99, 99.01)];
+    assert_eq!(percentiles.len(), expected.len());
+    for ((p, delay), (want_p, want_delay)) in percentiles.into_iter().zip(expected) {
+        assert_eq!(p, want_p);
+        assert!((delay - want_delay).abs() < 1e-9, "p{}: {} != {}", p * 100.0, delay, want_delay);
+    }
+
+    // No polls in range, and a percentile out of range
+    assert!(db.delay_percentiles(ROUTE, DAY_2, DAY_2, &[0.5]).await.unwrap().is_empty());
+    assert!(db.delay_percentiles(ROUTE, DAY_1 - 3600, DAY_1, &[90.0]).await.is_err());
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn coverage_history_per_poll() {
+    let Some(db) = test_db().await else { return };
+
+    let half = [departure("BBB:7023", "1001", DAY_1, Some(60)), departure("BBB:7024", "1001", DAY_1, None)];
+    log(&db, DAY_1 - 900, &half).await;
+    log(&db, DAY_1, &[departure("BBB:7023", "1002", DAY_1 + 900, None)]).await;
+    log(&db, DAY_1 + 900, &[departure("BBB:7023", "1003", DAY_1 + 1800, Some(0))]).await;
+
+    let history = db.coverage_history(DAY_1 - 900, DAY_1).await.unwrap();
+    assert_eq!(history, [(FeedTime::from_secs(DAY_1 - 900), 0.5), (FeedTime::from_secs(DAY_1), 0.0)]);
+    assert!(db.coverage_history(DAY_2, DAY_2).await.unwrap().is_empty());
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn trip_queries_follow_one_trip() {
+    let Some(db) = test_db().await else { return };
+
+    // Trip 1001 at two stops over two polls; 1002 only in the first poll
+    let first = [
+        departure("BBB:7024", "1001", DAY_1 + 300, Some(60)),
+        departure("BBB:7023", "1001", DAY_1, Some(60)),
+        departure("BBB:7023", "1002", DAY_1 + 900, None),
+    ];
+    log(&db, DAY_1 - 900, &first).await;
+    let second = [
+        departure("BBB:7024", "1001", DAY_1 + 300, Some(120)),
+        departure("BBB:7023", "1001", DAY_1, Some(120)),
+    ];
+    log(&db, DAY_1 - 300, &second).await;
+
+    let rows = db.departures_for_trip("1001").await.unwrap();
+    let seen: Vec<(i64, &str)> = rows
+        .iter()
+        .map(|r| (r.polled_at.secs(), r.departure.global_stop_id.as_str()))
+        .collect();
+    // Oldest poll first, stop order within a poll
+    assert_eq!(seen, [
+        (DAY_1 - 900, "BBB:7023"),
+        (DAY_1 - 900, "BBB:7024"),
+        (DAY_1 - 300, "BBB:7023"),
+        (DAY_1 - 300, "BBB:7024"),
+    ]);
+    assert!(db.departures_for_trip("9999").await.unwrap().is_empty());
+
+    let latest = db.latest_for_trip("1001").await.unwrap().expect("trip 1001 was logged");
+    assert_eq!(latest.polled_at.secs(), DAY_1 - 300);
+    assert_eq!(latest.departure.global_stop_id, "BBB:7023");
+    assert_eq!(latest.departure.delay_seconds, Some(120));
+    assert!(db.latest_for_trip("9999").await.unwrap().is_none());
+
+    // Strictly after `since`
+    assert_eq!(db.active_trips_since(DAY_1 - 1800).await.unwrap(), ["1001", "1002"]);
+    assert_eq!(db.active_trips_since(DAY_1 - 900).await.unwrap(), ["1001"]);
+    assert!(db.active_trips_since(DAY_1 - 300).await.unwrap().is_empty());
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn trips_with_cancellations_worst_first() {
+    let Some(db) = test_db().await else { return };
+
+    let cancelled = |stop: &str, trip: &str| {
+        let mut d = departure(stop, trip, DAY_1, Some(0));
+        d.is_cancelled = true;
+        d
+    };
+    // 1002 is cancelled at two stops; 1001 at one, reported by two polls
+    let first = [
+        cancelled("BBB:7023", "1001"),
+        cancelled("BBB:7023", "1002"),
+        departure("BBB:7023", "1003", DAY_1, Some(0)),
+    ];
+    log(&db, DAY_1 - 900, &first).await;
+    log(&db, DAY_1 - 300, &[cancelled("BBB:7023", "1001"), cancelled("BBB:7024", "1002")]).await;
+    // Outside the range, and on another route
+    log(&db, DAY_2 - 300, &[cancelled("BBB:7025", "1001")]).await;
+    let mut other_route = cancelled("BBB:7023", "2001");
+    other_route.global_route_id = "BBB:14430".to_string();
+    log(&db, DAY_1 - 300, &[other_route]).await;
+
+    let trips = db.trips_with_cancellations(ROUTE, DAY_1 - 900, DAY_1).await.unwrap();
+    assert_eq!(trips, [("1002".to_string(), 2), ("1001".to_string(), 1)]);
+    assert!(db.trips_with_cancellations(ROUTE, DAY_2, DAY_2).await.unwrap().is_empty());
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn delay_history_tracks_one_trip_at_one_stop() {
+    let Some(db) = test_db().await else { return };
+
+    log(&db, DAY_1 - 1800, &[departure("BBB:7023", "1001", DAY_1, Some(300))]).await;
+    // A poll with only the schedule is left out
+    log(&db, DAY_1 - 1200, &[departure("BBB:7023", "1001", DAY_1, None)]).await;
+    let both = [departure("BBB:7023", "1001", DAY_1, Some(180)), departure("BBB:7024", "1001", DAY_1, Some(999))];
+    log(&db, DAY_1 - 600, &both).await;
+    log(&db, DAY_1 - 300, &[departure("BBB:7023", "1002", DAY_1, Some(999))]).await;
+
+    let history = db.delay_history("1001", "BBB:7023").await.unwrap();
+    assert_eq!(history, [(FeedTime::from_secs(DAY_1 - 1800), 300), (FeedTime::from_secs(DAY_1 - 600), 180)]);
+    assert!(db.delay_history("1001", "BBB:9999").await.unwrap().is_empty());
+
+    db.drop_database().await;
+}
+
+#[tokio::test]
+async fn dropped_indexes_come_back_after_a_bulk_load() {
+    let Some(db) = test_db().await else { return };
+    let all = [
+        "departure_log_pkey", "idx_log_polled_at", "idx_log_stop", "idx_log_trip", "idx_log_unique",
+    ];
+    assert_eq!(db.indexes("departure_log").await, all);
+
+    // The unique index stays through the load, so duplicates are still skipped
+    db.drop_indexes().await.unwrap();
+    assert_eq!(db.indexes("departure_log").await, ["departure_log_pkey", "idx_log_unique"]);
+    let poll = [departure("BBB:7023", "1001", DAY_1, Some(60))];
+    assert_eq!(db.insert_departure_log(FeedTime::from_secs(DAY_1 - 900), &poll).await.unwrap(), 1);
+    assert_eq!(db.insert_departure_log(FeedTime::from_secs(DAY_1 - 900), &poll).await.unwrap(), 0);
+
+    db.create_indexes().await.unwrap();
+    assert_eq!(db.indexes("departure_log").await, all);
+    // Running it again with the indexes in place is a no-op
+    db.create_indexes().await.unwrap();
+    assert_eq!(db.indexes("departure_log").await, all);
+
+    db.drop_database().await;
+}
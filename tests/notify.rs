@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::State, routing::post, Json, Router};
+use bigbluebunch::notify::{detect_events, EventTracker};
+use bigbluebunch::{parse_stop_departures, FeedTime, MonitorEvent, Notifier, WebhookNotifier};
+
+const WESTWOOD: &[u8] = include_bytes!("fixtures/stop_departures_westwood.json");
+
+fn kinds(events: &[MonitorEvent]) -> Vec<String> {
+    events
+        .iter()
+        .map(|e| serde_json::to_value(e).unwrap()["kind"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn detects_each_kind_in_fixture() {
+    let deps = parse_stop_departures(WESTWOOD).unwrap();
+    let events = detect_events(&deps, 600, 120);
+    assert_eq!(kinds(&events), ["bunching", "severe_delay", "cancelled"]);
+    assert_eq!(events[1].to_string(), "Route 1 (UCLA) trip 1001 running 17m 0s late");
+    assert_eq!(
+        events[2].to_string(),
+        "Route 1 (Downtown Santa Monica) trip 1102 cancelled at 1 stop"
+    );
+}
+
+#[test]
+fn tracker_fires_on_transitions_only() {
+    let deps = parse_stop_departures(WESTWOOD).unwrap();
+    let mut tracker = EventTracker::new();
+
+    assert_eq!(tracker.transitions(detect_events(&deps, 600, 120)).len(), 3);
+    // Same conditions on the next poll: nothing new
+    assert!(tracker.transitions(detect_events(&deps, 600, 120)).is_empty());
+    // Conditions clear, then return
+    assert!(tracker.transitions(Vec::new()).is_empty());
+    assert_eq!(tracker.transitions(detect_events(&deps, 600, 120)).len(), 3);
+}
+
+#[test]
+fn rejects_non_http_webhook_urls() {
+    assert!(WebhookNotifier::new("ftp://example.com/hook").is_err());
+    assert!(WebhookNotifier::new("not a url").is_err());
+    assert!(WebhookNotifier::new("https://hooks.example.com/abc").is_ok());
+}
+
+type Received = Arc<Mutex<Vec<serde_json::Value>>>;
+
+async fn record(State(received): State<Received>, Json(body): Json<serde_json::Value>) {
+    received.lock().unwrap().push(body);
+}
+
+#[tokio::test]
+async fn webhook_posts_text_and_event() {
+    let received = Received::default();
+    let app = Router::new().route("/hook", post(record)).with_state(Arc::clone(&received));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let event = MonitorEvent::StaleFeed {
+        last_poll: Some(FeedTime::from_secs(1748123700)),
+        failing_secs: 1860,
+    };
+    WebhookNotifier::new(format!("http://{}/hook", addr)).unwrap().notify(&event).await.unwrap();
+
+    let got = received.lock().unwrap().clone();
+    assert_eq!(got.len(), 1);
+    assert_eq!(got[0]["text"], "Polls failing for 31 min (last success at 1748123700)");
+    assert_eq!(got[0]["content"], got[0]["text"]);
+    assert_eq!(got[0]["event"]["kind"], "stale_feed");
+    assert_eq!(got[0]["event"]["failing_secs"], 1860);
+
+    let missing = WebhookNotifier::new(format!("http://{}/nope", addr)).unwrap();
+    assert!(missing.notify(&event).await.is_err());
+}
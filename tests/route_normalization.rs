@@ -0,0 +1,57 @@
+use bigbluebunch::filter::route_id_mapping;
+use bigbluebunch::{normalize_route_id, RouteMatcher, RouteNormalization};
+
+fn bbb_rule() -> RouteNormalization {
+    RouteNormalization {
+        trim: true,
+        case_fold: true,
+        strip_prefixes: vec!["Rapid ".into()],
+        strip_leading_zeros: true,
+    }
+}
+
+#[test]
+fn default_rule_is_a_no_op() {
+    let rule = RouteNormalization::default();
+    for id in [" 7", "07", "Rapid 7", "R7"] {
+        assert_eq!(normalize_route_id(id, &rule), id);
+    }
+    assert!(matches!(RouteMatcher::exact(vec!["7".into()], &rule), RouteMatcher::Exact(_)));
+}
+
+#[test]
+fn applies_each_step() {
+    let rule = bbb_rule();
+    assert_eq!(normalize_route_id(" 7 ", &rule), "7");
+    assert_eq!(normalize_route_id("007", &rule), "7");
+    assert_eq!(normalize_route_id("RAPID 7", &rule), "7");
+    assert_eq!(normalize_route_id("R7", &rule), "r7");
+}
+
+#[test]
+fn never_normalizes_to_empty() {
+    let rule = bbb_rule();
+    assert_eq!(normalize_route_id("000", &rule), "0");
+    assert_eq!(normalize_route_id("Rapid ", &rule), "rapid");
+}
+
+#[test]
+fn normalized_matcher_and_mapping() {
+    let rule = bbb_rule();
+    let matcher = RouteMatcher::exact(vec!["7".into()], &rule);
+    assert!(matcher.matches("Rapid 7"));
+    assert!(matcher.matches("07"));
+    assert!(!matcher.matches("R7"));
+
+    let mapping = route_id_mapping(["07", "Rapid 7", "7", "R10"], &rule);
+    assert_eq!(mapping.len(), 2);
+    assert_eq!(mapping["7"].len(), 3);
+    assert!(mapping["r10"].contains("R10"));
+}
+
+#[test]
+fn rejects_empty_prefix() {
+    let rule = RouteNormalization { strip_prefixes: vec![" ".into()], ..RouteNormalization::default() };
+    assert!(rule.validate().is_err());
+    assert!(bbb_rule().validate().is_ok());
+}
@@ -0,0 +1,214 @@
+//! Captured stop_departures responses in `tests/fixtures/` replayed through
+//! the parser and the `DepartureSource` implementations, so parsing changes
+//! are checked against known data without touching the network.
+//!
+//! `stop_departures_westwood.json` covers two stops (BBB:7023, BBB:7041) on
+//! routes 1 and R12: a late bus with a second one 60 s behind it, a
+//! schedule-only trip, a cancelled trip, a `merged_headsign`, an early
+//! departure and an item with no trip ID.
+
+use std::path::PathBuf;
+
+use bigbluebunch::analysis::trips_exceeding_delay;
+use bigbluebunch::bunching::{bunching_across_polls, detect_bunching};
+use bigbluebunch::{
+    parse_file, parse_stop_departures, Departure, DepartureFilter, DepartureSource, FeedTime,
+    FileDepartureSource, LoggedDeparture, ParseOptions, RouteMatcher, StaticDepartureSource,
+};
+
+const WESTWOOD: &[u8] = include_bytes!("fixtures/stop_departures_westwood.json");
+const EMPTY: &[u8] = include_bytes!("fixtures/stop_departures_empty.json");
+/// Items with no departure time, zero timestamps and routes with no items
+const UNTIMED: &[u8] = include_bytes!("fixtures/stop_departures_untimed.json");
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn westwood() -> Vec<Departure> {
+    parse_stop_departures(WESTWOOD).expect("fixture parses")
+}
+
+fn trip<'a>(departures: &'a [Departure], stop: &str, trip_id: &str) -> &'a Departure {
+    departures
+        .iter()
+        .find(|d| d.global_stop_id == stop && d.rt_trip_id.as_deref() == Some(trip_id))
+        .unwrap_or_else(|| panic!("no departure for trip {} at {}", trip_id, stop))
+}
+
+#[test]
+fn parses_every_schedule_item() {
+    let deps = westwood();
+    assert_eq!(deps.len(), 8);
+    assert_eq!(deps.iter().filter(|d| d.global_stop_id == "BBB:7023").count(), 7);
+    assert_eq!(deps.iter().filter(|d| d.global_route_id == "BBB:14412").count(), 6);
+    assert!(parse_stop_departures(EMPTY).unwrap().is_empty());
+}
+
+#[test]
+fn departures_are_sorted_soonest_first() {
+    let deps = westwood();
+    assert!(deps.windows(2).all(|w| w[0].departure_time <= w[1].departure_time));
+    assert_eq!(deps[0].rt_trip_id.as_deref(), Some("1201"));
+}
+
+#[test]
+fn extracts_delays_from_scheduled_time() {
+    let deps = westwood();
+    assert_eq!(trip(&deps, "BBB:7023", "1001").delay_seconds, Some(1020));
+    assert_eq!(trip(&deps, "BBB:7023", "1002").delay_seconds, Some(180));
+    assert_eq!(trip(&deps, "BBB:7023", "1201").delay_seconds, Some(-60));
+    assert_eq!(trip(&deps, "BBB:7041", "1001").delay_seconds, Some(1020));
+
+    // Schedule-only departures carry no delay rather than a zero one
+    let scheduled = trip(&deps, "BBB:7023", "1003");
+    assert!(!scheduled.is_real_time);
+    assert_eq!(scheduled.delay_seconds, None);
+}
+
+#[test]
+fn missing_scheduled_time_falls_back_to_departure_time() {
+    let deps = westwood();
+    let no_trip = deps.iter().find(|d| d.rt_trip_id.is_none()).expect("item without trip ID");
+    assert_eq!(no_trip.route_short_name, "R12");
+    assert_eq!(no_trip.scheduled_departure_time, FeedTime::from_secs(1748125200));
+    assert_eq!(no_trip.delay_seconds, None);
+}
+
+#[test]
+fn extracts_headsign_and_cancellation() {
+    let deps = westwood();
+    assert_eq!(trip(&deps, "BBB:7023", "1001").headsign.as_deref(), Some("UCLA"));
+    // merged_headsign wins over headsign when present
+    assert_eq!(
+        trip(&deps, "BBB:7023", "1101").headsign.as_deref(),
+        Some("Downtown Santa Monica")
+    );
+    assert!(trip(&deps, "BBB:7023", "1102").is_cancelled);
+    assert_eq!(deps.iter().filter(|d| d.is_cancelled).count(), 1);
+}
+
+#[test]
+fn drops_departures_without_a_time() {
+    let deps = parse_stop_departures(UNTIMED).unwrap();
+    let trips: Vec<_> = deps.iter().filter_map(|d| d.rt_trip_id.as_deref()).collect();
+    assert_eq!(trips, ["2003", "2004"]);
+    assert!(deps.iter().all(|d| d.departure_time.is_set() && d.scheduled_departure_time.is_set()));
+
+    // A zero scheduled time counts as missing, not as a 55-year delay
+    assert_eq!(trip(&deps, "BBB:7023", "2003").delay_seconds, Some(0));
+}
+
+#[test]
+fn unset_times_format_as_unknown() {
+    let tz = chrono_tz::America::Los_Angeles;
+    let now = FeedTime::from_secs(1748123700);
+    assert_eq!(FeedTime::from_secs(0).format(&tz), "time unknown");
+    assert_eq!(FeedTime::from_secs(0).format_local(&tz, now), "time unknown");
+    assert_eq!(FeedTime::from_secs(i64::MAX).format(&tz), "invalid time");
+    assert_eq!(now.format_local(&tz, now), "14:55:00");
+}
+
+#[test]
+fn parse_options_drop_unwanted_departures() {
+    let options = ParseOptions { require_trip_id: true, ..ParseOptions::default() };
+    let deps = bigbluebunch::api::parse_stop_departures_with(WESTWOOD, &options).unwrap();
+    assert_eq!(deps.len(), 7);
+    assert!(deps.iter().all(|d| d.rt_trip_id.is_some()));
+
+    let options = ParseOptions { require_real_time: true, ..ParseOptions::default() };
+    let deps = bigbluebunch::api::parse_stop_departures_with(WESTWOOD, &options).unwrap();
+    assert_eq!(deps.len(), 6);
+    assert!(deps.iter().all(|d| d.is_real_time));
+}
+
+#[test]
+fn rejects_compressed_and_malformed_bodies() {
+    let err = parse_stop_departures(&[0x1f, 0x8b, 0x08, 0x00]).unwrap_err();
+    assert!(format!("{:#}", err).contains("gzip-compressed"));
+    assert!(parse_stop_departures(b"{\"route_departures\": 5}").is_err());
+}
+
+#[test]
+fn parse_file_matches_in_memory_parse() {
+    let from_disk = parse_file(&fixture_path("stop_departures_westwood.json")).unwrap();
+    assert_eq!(from_disk.len(), westwood().len());
+
+    let err = parse_file(&fixture_path("missing.json")).unwrap_err();
+    assert!(format!("{:#}", err).contains("missing.json"));
+}
+
+#[test]
+fn route_filtering() {
+    let deps = westwood();
+    let count = |filter: &DepartureFilter| deps.iter().filter(|d| filter.matches(d)).count();
+
+    assert_eq!(count(&DepartureFilter::routes(RouteMatcher::Exact(vec!["1".into()]))), 6);
+    assert_eq!(count(&DepartureFilter::routes(RouteMatcher::Prefix(vec!["R".into()]))), 2);
+    assert_eq!(count(&DepartureFilter::default()), deps.len());
+
+    let filter = DepartureFilter {
+        routes: Some(RouteMatcher::Exact(vec!["1".into()])),
+        headsigns: vec!["ucla".into()],
+        stop_ids: vec!["BBB:7023".into()],
+        ..DepartureFilter::default()
+    };
+    assert_eq!(count(&filter), 3);
+}
+
+#[test]
+fn analysis_over_fixture() {
+    let deps = westwood();
+
+    let late = trips_exceeding_delay(&deps, 300);
+    assert_eq!(late.len(), 1);
+    assert_eq!(late[0].rt_trip_id, "1001");
+    assert_eq!(late[0].max_delay_seconds, 1020);
+
+    // 1001 and 1002 leave BBB:7023 toward UCLA 60 s apart
+    let events = detect_bunching(&deps, 120);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].leading_trip_id.as_deref(), Some("1001"));
+    assert_eq!(events[0].trailing_trip_id.as_deref(), Some("1002"));
+    assert_eq!(events[0].gap_secs, 60);
+}
+
+#[test]
+fn bunching_counted_once_across_polls() {
+    // The same fixture logged by two polls a minute apart
+    let rows: Vec<LoggedDeparture> = [1748123000, 1748123060]
+        .into_iter()
+        .flat_map(|polled_at| westwood().into_iter().map(move |d| (polled_at, d)))
+        .enumerate()
+        .map(|(id, (polled_at, departure))| LoggedDeparture {
+            id: id as i64,
+            polled_at: FeedTime::from_secs(polled_at),
+            departure,
+        })
+        .collect();
+
+    let events = bunching_across_polls(&rows, 120);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].trailing_trip_id.as_deref(), Some("1002"));
+}
+
+#[tokio::test]
+async fn static_source_filters_to_requested_stops() {
+    let source = StaticDepartureSource::new(WESTWOOD);
+    assert_eq!(source.fetch_departures(&["BBB:7023", "BBB:7041"]).await.unwrap().len(), 8);
+
+    let deps = source.fetch_departures(&["BBB:7041"]).await.unwrap();
+    assert_eq!(deps.len(), 1);
+    assert_eq!(deps[0].rt_trip_id.as_deref(), Some("1001"));
+
+    assert!(source.fetch_departures(&["BBB:0000"]).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn file_source_reads_fixture() {
+    let source = FileDepartureSource::new(fixture_path("stop_departures_westwood.json"));
+    assert_eq!(source.fetch_departures(&["BBB:7023"]).await.unwrap().len(), 7);
+
+    let missing = FileDepartureSource::new(fixture_path("missing.json"));
+    assert!(missing.fetch_departures(&["BBB:7023"]).await.is_err());
+}